@@ -0,0 +1,273 @@
+mod inventory;
+mod prune;
+
+use clap::Arg;
+use clap_nested::{Command, Commander, MultiCommand};
+use dirs::home_dir;
+use std::fs;
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::config::{get_dist_path, get_tools_path, load_config_or_exit};
+use crate::package::checksum::{evict, verify_directory, CacheStatus};
+use crate::package::error::{EXIT_CHECKSUM, EXIT_DISK};
+use crate::package::PackageError;
+use crate::package::offline::is_dry_run;
+use crate::package::progress::{format_bytes, format_duration};
+use crate::package::signature::get_signature_path;
+use crate::receipt::load_receipts_or_exit;
+use inventory::{get_path_references, get_receipt_references, get_toolchain_references, get_tools_references, resolve_references, scan, Area, CacheEntry};
+use prune::{parse_days, parse_size, select_entries, PrunePolicy};
+
+/// Entries of dist and tools directories with references of registered installations, receipts
+/// and installed Rust toolchain.
+fn get_entries() -> Vec<CacheEntry> {
+    let config = load_config_or_exit();
+    let tools_path = get_tools_path();
+    let mut entries = scan(Path::new(&tools_path));
+    let mut paths = get_path_references(&config);
+    paths.extend(get_receipt_references(&load_receipts_or_exit()));
+    if let Some(home) = home_dir() {
+        paths.extend(get_toolchain_references(&home.join(".rustup"), Path::new(&tools_path)));
+    }
+    resolve_references(&mut entries, &paths, &get_tools_references(&config));
+    entries
+}
+
+fn get_total_size<'a, I: IntoIterator<Item = &'a CacheEntry>>(entries: I) -> u64 {
+    entries.into_iter().map(|entry| entry.size).sum()
+}
+
+fn format_age(entry: &CacheEntry, now: SystemTime) -> String {
+    let age = now.duration_since(entry.modified).unwrap_or_default();
+    let days = age.as_secs() / (24 * 60 * 60);
+    if days > 0 { format!("{}d", days) } else { format_duration(age) }
+}
+
+fn get_list_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let now = SystemTime::now();
+    let entries = get_entries();
+    for entry in entries.iter().filter(|entry| !matches.is_present("unreferenced") || !entry.is_referenced()) {
+        let references = if entry.is_referenced() { entry.references.join(", ") } else { "-".to_string() };
+        println!("{:<6} {:>10} {:>9}  {}  [{}]", entry.area.get_name(), format_bytes(entry.size),
+                 format_age(entry, now), entry.name, references);
+    }
+    println!("{} entries, {}.", entries.len(), format_bytes(get_total_size(&entries)));
+    Ok(())
+}
+
+pub fn get_list_cmd<'a>() -> Command<'a, str> {
+    Command::new("list")
+        .description("List archives in dist and tool versions in tools directory with size, age and references")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("unreferenced")
+                    .short("u")
+                    .long("unreferenced")
+                    .help("List only entries which are not used by any registered installation")
+            )
+        })
+        .runner(|_args, matches|
+            get_list_runner(_args, matches)
+        )
+}
+
+fn get_size_runner(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let entries = get_entries();
+    for area in [Area::Dist, Area::Tools] {
+        let area_entries: Vec<&CacheEntry> = entries.iter().filter(|entry| entry.area == area).collect();
+        let referenced = get_total_size(area_entries.iter().copied().filter(|entry| entry.is_referenced()));
+        let total = get_total_size(area_entries.iter().copied());
+        println!("{:<6} {:>10} in {} entries, {} referenced, {} unreferenced", area.get_name(), format_bytes(total),
+                 area_entries.len(), format_bytes(referenced), format_bytes(total - referenced));
+    }
+    println!("total  {:>10}", format_bytes(get_total_size(&entries)));
+    Ok(())
+}
+
+pub fn get_size_cmd<'a>() -> Command<'a, str> {
+    Command::new("size")
+        .description("Print disk space used by dist and tools directories")
+        .runner(|_args, matches|
+            get_size_runner(_args, matches)
+        )
+}
+
+fn remove_entry(entry: &CacheEntry) -> std::result::Result<(), PackageError> {
+    match entry.area {
+        // State of partial download is removed together with it
+        Area::Dist if entry.name.ends_with(".part") => {
+            fs::remove_file(&entry.path)?;
+            let _ = fs::remove_file(format!("{}.meta", entry.path.display()));
+            Ok(())
+        },
+        // Recorded digest and signature are removed together with the archive
+        Area::Dist => {
+            evict(&entry.path)?;
+            evict(Path::new(&get_signature_path(&entry.path.display().to_string())))
+        },
+        Area::Tools if entry.path.is_dir() => {
+            fs::remove_dir_all(&entry.path)?;
+            // Drop tools/<name> when its last version was removed
+            if let Some(tool_directory) = entry.path.parent().filter(|_| entry.name.contains('/')) {
+                let _ = fs::remove_dir(tool_directory);
+            }
+            Ok(())
+        },
+        Area::Tools => Ok(fs::remove_file(&entry.path)?),
+    }
+}
+
+fn get_prune_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let older_than = matches.value_of("older-than").map(|value| parse_days(value).unwrap_or_else(|| {
+        eprintln!("Invalid age: {}. Use number of days, e.g. 30 or 30d.", value);
+        std::process::exit(1);
+    }));
+    let max_size = matches.value_of("max-size").map(|value| parse_size(value).unwrap_or_else(|| {
+        eprintln!("Invalid size: {}. Use e.g. 500M or 10G.", value);
+        std::process::exit(1);
+    }));
+    if older_than.is_none() && max_size.is_none() {
+        eprintln!("Specify --older-than or --max-size.");
+        std::process::exit(1);
+    }
+    let dry_run = matches.is_present("dry-run") || is_dry_run();
+
+    let now = SystemTime::now();
+    let entries = get_entries();
+    let selected = select_entries(&entries, &PrunePolicy { older_than, max_size }, now);
+    // Entries which could not be removed still occupy the space
+    let mut removed = Vec::new();
+    let mut failed = 0;
+    for entry in selected {
+        println!("{} {}/{} ({}, {} old)", if dry_run { "Would remove" } else { "Removing" },
+                 entry.area.get_name(), entry.name, format_bytes(entry.size), format_age(entry, now));
+        if dry_run {
+            removed.push(entry);
+        } else {
+            match remove_entry(entry) {
+                Ok(_) => removed.push(entry),
+                Err(e) => {
+                    failed += 1;
+                    eprintln!("Unable to remove {}: {}", entry.path.display(), e);
+                },
+            }
+        }
+    }
+
+    let freed = get_total_size(removed.iter().copied());
+    let remaining = get_total_size(&entries) - freed;
+    println!("{} {} entries, {}. Remaining {}.", if dry_run { "Would free" } else { "Freed" },
+             removed.len(), format_bytes(freed), format_bytes(remaining));
+    if max_size.is_some_and(|max_size| remaining > max_size) {
+        if failed > 0 {
+            eprintln!("Size limit is still exceeded, {} entries could not be removed.", failed);
+        } else {
+            println!("Referenced entries alone exceed the size limit, they are kept.");
+        }
+    }
+    if failed > 0 {
+        std::process::exit(EXIT_DISK);
+    }
+    Ok(())
+}
+
+pub fn get_prune_cmd<'a>() -> Command<'a, str> {
+    Command::new("prune")
+        .description("Remove archives and tool versions which are not referenced by any registered installation")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("older-than")
+                    .short("a")
+                    .long("older-than")
+                    .help("Remove unreferenced entries older than number of days, e.g. 30")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("max-size")
+                    .short("s")
+                    .long("max-size")
+                    .help("Remove the oldest unreferenced entries until dist and tools fit into size, e.g. 10G")
+                    .takes_value(true)
+            )
+            .arg(
+                Arg::with_name("dry-run")
+                    .short("n")
+                    .long("dry-run")
+                    .help("Only print what would be removed")
+            )
+        })
+        .runner(|_args, matches|
+            get_prune_runner(_args, matches)
+        )
+}
+
+fn get_verify_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let dist_path = get_dist_path("");
+    let dist_directory = Path::new(&dist_path);
+    if !dist_directory.exists() {
+        println!("Download cache {} does not exist.", dist_path);
+        return Ok(());
+    }
+
+    let results = match verify_directory(dist_directory) {
+        Ok(results) => results,
+        Err(e) => {
+            e.exit(&format!("Unable to verify {}: ", dist_path));
+        }
+    };
+
+    let mut failed = 0;
+    for (file_name, status) in &results {
+        match status {
+            CacheStatus::Valid => println!("[OK]         {}", file_name),
+            CacheStatus::Unrecorded => println!("[UNRECORDED] {}", file_name),
+            CacheStatus::Missing => println!("[MISSING]    {}", file_name),
+            CacheStatus::Mismatch(digest) => {
+                failed += 1;
+                println!("[MISMATCH]   {} (SHA-256 {})", file_name, digest);
+                if matches.is_present("evict") {
+                    match evict(&dist_directory.join(file_name)) {
+                        Ok(_) => println!("             removed"),
+                        Err(e) => println!("             {}", e),
+                    }
+                }
+            },
+        }
+    }
+    println!("Verified {} file(s), {} mismatch(es).", results.len(), failed);
+    if failed > 0 && !matches.is_present("evict") {
+        std::process::exit(EXIT_CHECKSUM);
+    }
+    Ok(())
+}
+
+pub fn get_verify_cmd<'a>() -> Command<'a, str> {
+    Command::new("verify")
+        .description("Verify downloaded archives in dist directory against recorded SHA-256 digests")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("evict")
+                    .short("e")
+                    .long("evict")
+                    .help("Remove archives which do not match, they will be downloaded again")
+            )
+        })
+        .runner(|_args, matches|
+            get_verify_runner(_args, matches)
+        )
+}
+
+pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
+    let multi_cmd: MultiCommand<str, str> = Commander::new()
+        .add_cmd(get_list_cmd())
+        .add_cmd(get_prune_cmd())
+        .add_cmd(get_size_cmd())
+        .add_cmd(get_verify_cmd())
+        .into_cmd("cache")
+
+        // Optionally specify a description
+        .description("Inspect, verify and prune downloaded archives and installed tools.");
+
+    return multi_cmd;
+}
@@ -0,0 +1,296 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use walkdir::WalkDir;
+
+use crate::config::EspIdfConfig;
+use crate::idf::tools::ToolsManifest;
+use crate::package::checksum::is_manifest_file;
+use crate::package::signature::SIGNATURE_EXTENSION;
+use crate::receipt::Receipt;
+use crate::shell::get_env_variable;
+
+/// Tools which the Rust toolchain for Xtensa, installed by `rust install`, needs at runtime.
+const RUST_TOOLCHAIN_TOOLS: &[&str] = &["xtensa-esp32-elf-clang", "mingw"];
+
+/// Directory of IDF_TOOLS_PATH managed by the cache command.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Area {
+    Dist,
+    Tools,
+}
+
+impl Area {
+    pub fn get_name(&self) -> &'static str {
+        match self {
+            Area::Dist => "dist",
+            Area::Tools => "tools",
+        }
+    }
+}
+
+/// Downloaded archive in dist directory or installed tool version in tools directory.
+#[derive(Clone, Debug)]
+pub struct CacheEntry {
+    pub area: Area,
+    /// Path relative to the area directory, e.g. llvm.tar.xz or idf-git/2.30.1.
+    pub name: String,
+    pub path: PathBuf,
+    pub size: u64,
+    pub modified: SystemTime,
+    /// What still uses the entry, e.g. installation id or gitPath. Referenced entries are never pruned.
+    pub references: Vec<String>,
+}
+
+impl CacheEntry {
+    pub fn is_referenced(&self) -> bool {
+        !self.references.is_empty()
+    }
+}
+
+/// Manifest, state of partial downloads and signatures belong to other entries, they are not listed on their own.
+/// Partial download is listed, so that a stale one can be pruned.
+fn is_control_file(file_name: &str) -> bool {
+    is_manifest_file(file_name) || file_name.ends_with(".part.meta") || file_name.ends_with(SIGNATURE_EXTENSION)
+}
+
+fn get_size(path: &Path) -> u64 {
+    WalkDir::new(path).into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn create_entry(area: Area, name: String, path: PathBuf) -> Option<CacheEntry> {
+    let modified = fs::symlink_metadata(&path).and_then(|metadata| metadata.modified()).ok()?;
+    let size = get_size(&path);
+    Some(CacheEntry { area, name, path, size, modified, references: Vec::new() })
+}
+
+fn read_sorted_dir(directory: &Path) -> Vec<fs::DirEntry> {
+    let mut entries: Vec<fs::DirEntry> = match fs::read_dir(directory) {
+        Ok(entries) => entries.filter_map(|entry| entry.ok()).collect(),
+        Err(_e) => return Vec::new(),
+    };
+    entries.sort_by_key(|entry| entry.file_name());
+    entries
+}
+
+fn scan_dist(dist_directory: &Path) -> Vec<CacheEntry> {
+    read_sorted_dir(dist_directory).into_iter()
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|file_name| !is_control_file(file_name))
+        .filter_map(|file_name| create_entry(Area::Dist, file_name.clone(), dist_directory.join(file_name)))
+        .collect()
+}
+
+/// Tools are stored as tools/<name>/<version> like ESP-IDF does. Directory which contains files directly,
+/// e.g. temporary extraction of rust, is one entry.
+fn scan_tools(tools_directory: &Path) -> Vec<CacheEntry> {
+    let mut entries = Vec::new();
+    for tool in read_sorted_dir(tools_directory) {
+        let tool_name = tool.file_name().to_string_lossy().to_string();
+        let tool_path = tool.path();
+        let versions = read_sorted_dir(&tool_path);
+        if !tool_path.is_dir() || versions.is_empty() || versions.iter().any(|version| !version.path().is_dir()) {
+            entries.extend(create_entry(Area::Tools, tool_name, tool_path));
+            continue;
+        }
+        for version in versions {
+            let name = format!("{}/{}", tool_name, version.file_name().to_string_lossy());
+            entries.extend(create_entry(Area::Tools, name, version.path()));
+        }
+    }
+    entries
+}
+
+/// Entries of dist and tools directories under tools_path, without references.
+pub fn scan(tools_path: &Path) -> Vec<CacheEntry> {
+    let mut entries = scan_dist(&tools_path.join("dist"));
+    entries.extend(scan_tools(&tools_path.join("tools")));
+    entries
+}
+
+/// Path which keeps cache entries alive, e.g. git binary or directory on PATH.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PathReference {
+    pub path: PathBuf,
+    pub source: String,
+}
+
+/// Tools and archives listed in tools/tools.json of an ESP-IDF installation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToolsReference {
+    pub source: String,
+    /// Tool directories in form <name>/<version>.
+    pub tools: BTreeSet<String>,
+    /// File names of archives for all platforms.
+    pub archives: BTreeSet<String>,
+}
+
+/// Tool versions and archives from content of ESP-IDF tools.json.
+pub fn parse_tools_json(content: &str, source: &str) -> Option<ToolsReference> {
+    let manifest = ToolsManifest::parse(content).ok()?;
+    let mut reference = ToolsReference { source: source.to_string(), ..Default::default() };
+    for tool in &manifest.tools {
+        for version in &tool.versions {
+            reference.tools.insert(format!("{}/{}", tool.name, version.name));
+            reference.archives.extend(version.downloads.values().map(|download| download.get_file_name().to_string()));
+        }
+    }
+    Some(reference)
+}
+
+fn push_path(references: &mut Vec<PathReference>, path: &str, source: String) {
+    if !path.is_empty() {
+        references.push(PathReference { path: PathBuf::from(path), source });
+    }
+}
+
+/// Paths registered in esp_idf.json and in persisted user environment variables. Environment of the current
+/// process is not used, so that the result does not depend on the shell which runs the command.
+pub fn get_path_references(config: &EspIdfConfig) -> Vec<PathReference> {
+    let mut references = Vec::new();
+    push_path(&mut references, &config.git_path, "gitPath".to_string());
+    for (idf_id, installation) in &config.idf_installed {
+        push_path(&mut references, &installation.python, format!("{} python", idf_id));
+        push_path(&mut references, &installation.path, format!("{} path", idf_id));
+    }
+    // Installed Rust toolchain finds LLVM and MinGW on PATH or by LIBCLANG_PATH
+    for variable in ["PATH", "LIBCLANG_PATH"] {
+        for path in get_env_variable(variable).unwrap_or_default().split(';') {
+            push_path(&mut references, path, format!("user {}", variable));
+        }
+    }
+    references
+}
+
+/// Versions of LLVM and MinGW are kept while the esp toolchain exists in rustup, also when the toolchain
+/// was installed by idf-env without receipts.
+pub fn get_toolchain_references(rustup_home: &Path, tools_path: &Path) -> Vec<PathReference> {
+    let toolchain = rustup_home.join("toolchains").join("esp");
+    if !toolchain.is_dir() {
+        return Vec::new();
+    }
+    let mut references = Vec::new();
+    for tool_name in RUST_TOOLCHAIN_TOOLS {
+        for version in read_sorted_dir(&tools_path.join("tools").join(tool_name)) {
+            references.push(PathReference { path: version.path(), source: format!("rust toolchain {}", toolchain.display()) });
+        }
+    }
+    references
+}
+
+/// Paths created by installations recorded in receipts, e.g. MinGW which is not on PATH of the current shell.
+pub fn get_receipt_references(receipts: &[Receipt]) -> Vec<PathReference> {
+    let mut references = Vec::new();
+    for receipt in receipts {
+        for path in &receipt.paths {
+            push_path(&mut references, path, format!("{} receipt", receipt.get_key()));
+        }
+    }
+    references
+}
+
+/// Tools.json of each registered ESP-IDF installation.
+pub fn get_tools_references(config: &EspIdfConfig) -> Vec<ToolsReference> {
+    config.idf_installed.iter()
+        .filter_map(|(idf_id, installation)| {
+            let content = fs::read_to_string(Path::new(&installation.path).join("tools").join("tools.json")).ok()?;
+            parse_tools_json(&content, idf_id)
+        })
+        .collect()
+}
+
+/// Lexical comparison, paths from configuration may be written with either separator.
+fn normalize(path: &Path) -> String {
+    let text = path.display().to_string().replace('\\', "/");
+    let text = text.trim_end_matches('/');
+    if cfg!(windows) { text.to_lowercase() } else { text.to_string() }
+}
+
+fn is_inside(path: &Path, directory: &Path) -> bool {
+    let (path, directory) = (normalize(path), normalize(directory));
+    path == directory || path.starts_with(&format!("{}/", directory))
+}
+
+/// Fill references of entries. Tool directory is referenced when a registered path points inside it
+/// or an installation lists its version, archive is referenced when an installation lists its file name.
+pub fn resolve_references(entries: &mut [CacheEntry], paths: &[PathReference], tools: &[ToolsReference]) {
+    for entry in entries.iter_mut() {
+        let mut references = Vec::new();
+        for reference in paths.iter().filter(|reference| is_inside(&reference.path, &entry.path)) {
+            references.push(reference.source.clone());
+        }
+        for reference in tools {
+            let listed = match entry.area {
+                Area::Dist => reference.archives.contains(&entry.name),
+                Area::Tools => reference.tools.contains(&entry.name),
+            };
+            if listed {
+                references.push(format!("{} tools.json", reference.source));
+            }
+        }
+        references.dedup();
+        entry.references = references;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::package::checksum::MANIFEST_NAME;
+
+    #[test]
+    fn test_scan_and_resolve_references() {
+        let directory = std::env::temp_dir().join(format!("idf-env-inventory-{}", std::process::id()));
+        let tools = directory.join("tools");
+        fs::create_dir_all(directory.join("dist")).unwrap();
+        fs::create_dir_all(tools.join("idf-git/2.30.1/cmd")).unwrap();
+        fs::create_dir_all(tools.join("xtensa-esp-elf/esp-12.2.0")).unwrap();
+        fs::create_dir_all(tools.join("rust/lib")).unwrap();
+        fs::write(tools.join("rust/install.sh"), "#!/bin/sh").unwrap();
+        fs::write(tools.join("idf-git/2.30.1/cmd/git.exe"), "git").unwrap();
+        fs::write(directory.join("dist/idf-git-2.30.1.zip"), "archive").unwrap();
+        fs::write(directory.join("dist/xtensa-esp-elf-12.2.0.tar.xz"), "archive").unwrap();
+        fs::write(directory.join("dist/llvm.tar.xz.part"), "partial").unwrap();
+        fs::write(directory.join("dist/llvm.tar.xz.part.meta"), "etag").unwrap();
+        fs::write(directory.join("dist/idf-git-2.30.1.zip.minisig"), "signature").unwrap();
+        fs::write(directory.join("dist").join(MANIFEST_NAME), "").unwrap();
+
+        let mut entries = scan(&directory);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["idf-git-2.30.1.zip", "llvm.tar.xz.part", "xtensa-esp-elf-12.2.0.tar.xz", "idf-git/2.30.1", "rust", "xtensa-esp-elf/esp-12.2.0"]);
+        assert_eq!(entries[3].size, 3);
+
+        let tools_json = r#"{"tools": [{"name": "xtensa-esp-elf", "versions": [{"name": "esp-12.2.0",
+            "linux-amd64": {"url": "https://github.com/espressif/crosstool-NG/releases/download/esp-12.2.0/xtensa-esp-elf-12.2.0.tar.xz"}}]}]}"#;
+        let paths = vec![PathReference { path: tools.join("idf-git\\2.30.1/cmd/git.exe"), source: "gitPath".to_string() }];
+        resolve_references(&mut entries, &paths, &[parse_tools_json(tools_json, "esp-idf-1").unwrap()]);
+        let referenced: Vec<&str> = entries.iter().filter(|entry| entry.is_referenced()).map(|entry| entry.name.as_str()).collect();
+        assert_eq!(referenced, vec!["xtensa-esp-elf-12.2.0.tar.xz", "idf-git/2.30.1", "xtensa-esp-elf/esp-12.2.0"]);
+        assert_eq!(entries[3].references, vec!["gitPath".to_string()]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_toolchain_references() {
+        let directory = std::env::temp_dir().join(format!("idf-env-toolchain-{}", std::process::id()));
+        let rustup_home = directory.join(".rustup");
+        fs::create_dir_all(directory.join("tools/xtensa-esp32-elf-clang/esp-15.0.0-20221014-x86_64-unknown-linux-gnu")).unwrap();
+        fs::create_dir_all(directory.join("tools/idf-git/2.30.1")).unwrap();
+        assert!(get_toolchain_references(&rustup_home, &directory).is_empty());
+
+        fs::create_dir_all(rustup_home.join("toolchains/esp")).unwrap();
+        let mut entries = scan(&directory);
+        resolve_references(&mut entries, &get_toolchain_references(&rustup_home, &directory), &[]);
+        let referenced: Vec<&str> = entries.iter().filter(|entry| entry.is_referenced()).map(|entry| entry.name.as_str()).collect();
+        assert_eq!(referenced, vec!["xtensa-esp32-elf-clang/esp-15.0.0-20221014-x86_64-unknown-linux-gnu"]);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
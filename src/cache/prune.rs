@@ -0,0 +1,111 @@
+use std::time::{Duration, SystemTime};
+
+use super::inventory::CacheEntry;
+
+/// Unreferenced entries older than `older_than` are removed, then the oldest unreferenced entries
+/// are removed until the cache fits into `max_size`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrunePolicy {
+    pub older_than: Option<Duration>,
+    pub max_size: Option<u64>,
+}
+
+/// Size like 500M, 10G or 1.5GiB, units are powers of 1024. Number without unit is in bytes.
+pub fn parse_size(value: &str) -> Option<u64> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let number: f64 = number.parse().ok()?;
+    let exponent = match unit.trim().to_lowercase().as_str() {
+        "" | "b" => 0,
+        "k" | "kb" | "kib" => 1,
+        "m" | "mb" | "mib" => 2,
+        "g" | "gb" | "gib" => 3,
+        "t" | "tb" | "tib" => 4,
+        _ => return None,
+    };
+    Some((number * 1024f64.powi(exponent)) as u64)
+}
+
+/// Age in days, e.g. 30 or 30d.
+pub fn parse_days(value: &str) -> Option<Duration> {
+    let days: u64 = value.trim().trim_end_matches('d').parse().ok()?;
+    Some(Duration::from_secs(days * 24 * 60 * 60))
+}
+
+fn get_age(entry: &CacheEntry, now: SystemTime) -> Duration {
+    now.duration_since(entry.modified).unwrap_or_default()
+}
+
+/// Entries to remove by the policy, referenced entries are never selected.
+pub fn select_entries<'a>(entries: &'a [CacheEntry], policy: &PrunePolicy, now: SystemTime) -> Vec<&'a CacheEntry> {
+    let mut candidates: Vec<&CacheEntry> = entries.iter().filter(|entry| !entry.is_referenced()).collect();
+    candidates.sort_by_key(|entry| entry.modified);
+
+    let (expired, mut kept): (Vec<&CacheEntry>, Vec<&CacheEntry>) = candidates.into_iter()
+        .partition(|entry| policy.older_than.is_some_and(|older_than| get_age(entry, now) >= older_than));
+    let mut selected = expired;
+    if let Some(max_size) = policy.max_size {
+        let mut remaining: u64 = entries.iter().map(|entry| entry.size).sum::<u64>()
+            - selected.iter().map(|entry| entry.size).sum::<u64>();
+        kept.reverse();
+        while remaining > max_size {
+            match kept.pop() {
+                Some(entry) => {
+                    remaining -= entry.size;
+                    selected.push(entry);
+                },
+                None => break,
+            }
+        }
+    }
+    selected
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::inventory::Area;
+    use std::path::PathBuf;
+
+    fn get_entry(name: &str, size: u64, age_days: u64, referenced: bool, now: SystemTime) -> CacheEntry {
+        CacheEntry {
+            area: Area::Dist,
+            name: name.to_string(),
+            path: PathBuf::from(name),
+            size,
+            modified: now - parse_days(&age_days.to_string()).unwrap(),
+            references: if referenced { vec!["esp-idf-1 tools.json".to_string()] } else { Vec::new() },
+        }
+    }
+
+    #[test]
+    fn test_select_entries() {
+        let now = SystemTime::now();
+        let entries = vec![
+            get_entry("old-referenced", 100, 90, true, now),
+            get_entry("old", 10, 60, false, now),
+            get_entry("recent", 20, 5, false, now),
+            get_entry("newest", 30, 1, false, now),
+        ];
+        let names = |policy: PrunePolicy| -> Vec<String> {
+            select_entries(&entries, &policy, now).iter().map(|entry| entry.name.clone()).collect()
+        };
+
+        assert!(names(PrunePolicy::default()).is_empty());
+        assert_eq!(names(PrunePolicy { older_than: parse_days("30d"), max_size: None }), vec!["old"]);
+        assert_eq!(names(PrunePolicy { older_than: None, max_size: Some(135) }), vec!["old", "recent"]);
+        assert_eq!(names(PrunePolicy { older_than: parse_days("30"), max_size: Some(130) }), vec!["old", "recent"]);
+        // Referenced entries are kept even when the cache does not fit
+        assert_eq!(names(PrunePolicy { older_than: None, max_size: Some(0) }), vec!["old", "recent", "newest"]);
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("10G"), Some(10 * 1024 * 1024 * 1024));
+        assert_eq!(parse_size("1.5 MiB"), Some(1536 * 1024));
+        assert_eq!(parse_size("5 parsecs"), None);
+        assert_eq!(parse_days("x"), None);
+    }
+}
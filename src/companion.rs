@@ -10,9 +10,10 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>
 fn prepare_companion() {
     match prepare_package("https://dl.espressif.com/dl/esp-iwidc/esp-iwidc.zip".to_string(),
                     "esp-iwidc.zip",
-                    "tmp/esp-iwidc".to_string()) {
+                    "tmp/esp-iwidc".to_string(),
+                    None) {
                         Ok(_) => { println!("Ok"); },
-                        Err(_e) => { println!("Failed");}
+                        Err(e) => { e.exit("Failed: "); }
                     }
 }
 
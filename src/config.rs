@@ -1,19 +1,38 @@
 extern crate json;
 
+mod discover;
+mod doctor;
+mod layers;
+mod model;
+mod pin;
+mod portable;
+pub mod storage;
+
 use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use md5;
 use std::env;
 use dirs::home_dir;
-use json::JsonValue;
 use crate::shell::run_command;
 
+pub use model::{EspIdfConfig, IdfInstallation};
+pub use pin::ResolvedIdf;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// IDF_TOOLS_PATH, otherwise idfToolsPath of environment, project or system configuration layer, otherwise ~/.espressif.
+/// IDF_TOOLS_PATH, idfToolsPath of configuration layers or ~/.espressif. Command terminates when
+/// a configuration layer is corrupted, the same as when the configuration is loaded.
 pub fn get_tools_path() -> String {
-    env::var("IDF_TOOLS_PATH").unwrap_or_else(|_e|
-        home_dir().unwrap().display().to_string() + "/.espressif"
-    )
+    env::var("IDF_TOOLS_PATH")
+        .ok()
+        .or_else(|| layers::get_layered_tools_path().unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }))
+        .unwrap_or_else(|| home_dir().unwrap().display().to_string() + "/.espressif")
 }
 
 pub fn get_tool_path(tool_name:String) -> String {
@@ -31,100 +50,322 @@ pub fn get_python_env_path(idf_version: String, python_version: String) -> Strin
     format!("{}/python_env/idf{}_py{}_env", tools_path, idf_version, python_version)
 }
 
-pub fn get_selected_idf_path() -> String {
-    let selected_idf_id = get_property("idfSelectedId".to_string());
-    get_property_with_idf_id("path".to_string(), selected_idf_id)
-}
-
 fn get_json_path() -> String {
     let idf_json_path = format!("{}/esp_idf.json", get_tools_path());
     return idf_json_path;
 }
 
+/// Transform version like "Python 3.8.7" or "v5.1.2" to "3.8" or "5.1".
+pub fn get_version_major_minor(version_text: &str) -> String {
+    let version = version_text.split_whitespace().last().unwrap_or("");
+    version.trim_start_matches('v').split('.').take(2)
+        .map(|part| part.chars().take_while(|c| c.is_ascii_digit()).collect::<String>())
+        .collect::<Vec<String>>()
+        .join(".")
+}
+
 pub fn get_idf_id(idf_path: &str) -> String {
     let idf_path_with_slash = format!("{}", idf_path.replace("\\", "/"));
     let digest = md5::compute(idf_path_with_slash);
     return format!("esp-idf-{:x}", digest);
 }
 
-fn bootstrap_json(_json_path: String, tools_path: String) {
-    let template = json::object!{
-        "$schema": "http://json-schema.org/schema#",
-        "$id": "http://dl.espressif.com/dl/schemas/esp_idf",
-        "_comment": "Configuration file for ESP-IDF Eclipse plugin.",
-        "_warning": "Use / or \\ when specifying path. Single backslash is not allowed by JSON format.",
-        "gitPath": "",
-        "idfToolsPath": tools_path,
-        "idfSelectedId": "",
-        "idfInstalled": json::JsonValue::new_object()
+fn bootstrap_json(json_path: String, tools_path: String) -> Result<()> {
+    save_config_to(&json_path, &EspIdfConfig::new(tools_path))
+}
+
+fn save_config_to(json_path: &str, config: &EspIdfConfig) -> Result<()> {
+    storage::write_atomic(json_path, &format!("{:#}", config.to_json()))
+}
+
+/// Read and parse esp_idf.json, the flag in result is set when the content was migrated to the current format.
+/// Caller is responsible for holding the lock when the result is written back.
+fn read_config(json_path: &str) -> Result<(EspIdfConfig, bool)> {
+    let content = fs::read_to_string(json_path)
+        .map_err(|e| format!("Unable to read {}: {}", json_path, e))?;
+    match EspIdfConfig::parse_and_migrate(&content) {
+        Ok(result) => Ok(result),
+        Err(parse_error) => {
+            let backup = match storage::backup_corrupted(json_path) {
+                Ok(backup_path) => format!("Copy of the file was stored in {}.", backup_path),
+                Err(_e) => "Unable to create backup copy of the file.".to_string()
+            };
+            Err(format!("Configuration file {} is corrupted: {}. {} Fix the file or remove it to start with empty configuration.",
+                        json_path, parse_error, backup).into())
+        }
+    }
+}
+
+/// Load esp_idf.json, create it when missing and upgrade it to the current format version.
+/// The result is merged with system and project configuration files and IDF_ENV_* overrides.
+fn load_layered_config() -> Result<layers::LayeredConfig> {
+    let json_path = get_json_path();
+    if Path::new(&json_path).exists() {
+        let (config, migrated) = read_config(&json_path)?;
+        if !migrated {
+            return layers::merge_layers(&config, &json_path, get_tools_path());
+        }
+    }
+
+    // File is missing or stored in older format, create or upgrade it under the lock
+    update_config(|_config| ())?;
+    layers::merge_layers(&read_config(&json_path)?.0, &json_path, get_tools_path())
+}
+
+/// Effective configuration, see load_layered_config. Modifications must go through update_config.
+pub fn load_config() -> Result<EspIdfConfig> {
+    Ok(load_layered_config()?.config)
+}
+
+/// Effective configuration for reading settings, esp_idf.json is neither created nor migrated on disk.
+pub fn peek_config() -> Result<EspIdfConfig> {
+    let json_path = get_json_path();
+    let user_config = match fs::read_to_string(&json_path) {
+        Ok(content) => EspIdfConfig::parse_and_migrate(&content)
+            .map_err(|e| format!("Configuration file {} is corrupted: {}", json_path, e))?.0,
+        Err(_e) => EspIdfConfig::new(get_tools_path()),
     };
-    fs::write(get_json_path(), template.to_string()).unwrap();
+    Ok(layers::merge_layers(&user_config, &json_path, get_tools_path())?.config)
 }
 
-fn load_json() -> json::JsonValue {
+/// Load configuration for a command, terminate with explanation when the file is not usable.
+pub fn load_config_or_exit() -> EspIdfConfig {
+    match load_config() {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Apply modification to the configuration and store the result.
+/// Whole read-modify-write cycle runs under the configuration lock.
+/// Only the user layer stored in esp_idf.json is modified, other layers are read-only.
+pub fn update_config<T, F: FnOnce(&mut EspIdfConfig) -> T>(modify: F) -> Result<T> {
     let json_path = get_json_path();
+    let _lock = storage::ConfigLock::acquire(&json_path)?;
     if !Path::new(&json_path).exists() {
-        println!("Configuration file not found, creating new one: {}", json_path);
-        bootstrap_json(json_path.clone(), get_tools_path());
+        eprintln!("Configuration file not found, creating new one: {}", json_path);
+        bootstrap_json(json_path.clone(), get_tools_path())?;
     }
-
-    let content = fs::read_to_string(json_path)
-        .expect("Failure");
-    return json::parse(&content.to_string()).unwrap();
+    let (mut config, _) = read_config(&json_path)?;
+    let result = modify(&mut config);
+    save_config_to(&json_path, &config)?;
+    Ok(result)
 }
 
-pub fn get_property(property_name: String) -> String {
-    let parsed_json = load_json();
-    return parsed_json[property_name].to_string();
+fn print_property(property_name: String, show_source: bool) {
+    let layered = load_layered_config().unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    match layered.config.get_property(&property_name) {
+        Some(value) => print!("{}", value),
+        None => {
+            eprintln!("Unknown property: {}", property_name);
+            std::process::exit(1);
+        }
+    }
+    if show_source {
+        println!();
+        println!("Source: {}", layered.get_origin(&property_name));
+    }
 }
 
-fn print_property(property_name: String) {
-    print!("{}", &get_property(property_name));
+/// Print configuration files in the order of increasing priority.
+fn print_layers() {
+    let project_path = layers::find_project_config()
+        .map(|path| path.display().to_string())
+        .unwrap_or_else(|| format!("{} not found", layers::PROJECT_CONFIG_NAME));
+    let system_path = layers::get_system_config_path();
+    println!("system:      {} ({})", system_path, get_existence_flag(&system_path));
+    println!("user:        {} ({})", get_json_path(), get_existence_flag(&get_json_path()));
+    println!("project:     {}", project_path);
+    println!("environment: {}", layers::get_environment_variable_names().join(", "));
 }
 
 pub fn get_git_path() -> String {
-    get_property("gitPath".to_string())
+    load_config_or_exit().git_path
 }
 
-pub fn get_property_with_idf_id(property_name: String, idf_id: String) -> String {
-    let parsed_json = load_json();
-    return parsed_json["idfInstalled"][idf_id][property_name].to_string();
+fn print_property_with_id(property_name: String, idf_id: String, show_source: bool) {
+    let layered = load_layered_config().unwrap_or_else(|e| {
+        println!("{}", e);
+        std::process::exit(1);
+    });
+    match layered.config.get_installation(&idf_id) {
+        Some(installation) => {
+            match installation.get_property(&property_name) {
+                Some(value) => print!("{}", value),
+                None => {
+                    eprintln!("Unknown property: {}", property_name);
+                    std::process::exit(1);
+                }
+            }
+            if show_source {
+                println!();
+                println!("Source: {}", layered.get_installation_origin(&idf_id));
+            }
+        },
+        None => {
+            eprintln!("ESP-IDF installation not found: {}", idf_id);
+            std::process::exit(1);
+        }
+    }
 }
 
-
-pub fn get_property_with_path(property_name: String, idf_path: String) -> String {
-    let parsed_json = load_json();
+/// Register ESP-IDF installation and optionally make it the selected one. Name must be unique among installations.
+pub fn add_idf_config(idf_path: String, version: String, python_path: String, name: String, select: bool) -> Result<()> {
     let idf_id = get_idf_id(&idf_path);
-    return parsed_json["idfInstalled"][idf_id][property_name].to_string();
+    update_config(|config| -> Result<()> {
+        if let Some(other_id) = config.find_id_by_name(&name) {
+            if other_id != idf_id {
+                return Err(format!("Name '{}' is already used by installation {}", name, other_id).into());
+            }
+        }
+        let mut installation = IdfInstallation::new(idf_path, version, python_path);
+        installation.name = name;
+        config.idf_installed.insert(idf_id.clone(), installation);
+        if select {
+            config.idf_selected_id = idf_id;
+        }
+        Ok(())
+    })?
+}
+
+/// Report failure of a command and terminate with non-zero exit code.
+fn exit_on_error(result: Result<()>) {
+    if let Err(e) = result {
+        println!("{}", e);
+        std::process::exit(1);
+    }
 }
 
-fn print_property_with_path(property_name: String, idf_path: String) {
-    print!("{}", get_property_with_path(property_name, idf_path));
+fn normalize_path(path: &str) -> String {
+    path.replace("\\", "/").trim_end_matches('/').to_string()
 }
 
-fn print_property_with_id(property_name: String, idf_id: String) {
-    print!("{}", get_property_with_idf_id(property_name, idf_id));
+fn find_idf_id_by_path(config: &EspIdfConfig, idf_path: &str) -> Option<String> {
+    let normalized_path = normalize_path(idf_path);
+    config.idf_installed.iter()
+        .find(|(_, installation)| normalize_path(&installation.path) == normalized_path)
+        .map(|(idf_id, _)| idf_id.clone())
 }
 
-pub fn update_property(property_name: String, property_value: String) {
-    let mut parsed_json = load_json();
-    parsed_json[property_name] = JsonValue::String(property_value);
-    fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
+/// Find ID of registered installation referenced by its ID, name or path.
+pub fn find_idf_id(config: &EspIdfConfig, reference: &str) -> Option<String> {
+    if config.idf_installed.contains_key(reference) {
+        return Some(reference.to_string());
+    }
+    config.find_id_by_name(reference)
+        .or_else(|| find_idf_id_by_path(config, reference))
 }
 
-pub fn add_idf_config(idf_path: String, version: String, python_path: String) {
-    let idf_id = get_idf_id(&idf_path);
-    let _data = json::object! {
-        version: version,
-        python: python_path,
-        path: idf_path
-    };
+/// Resolve installation from --idf-id, --idf-path, --name or positional reference of a command.
+pub fn resolve_idf_id(config: &EspIdfConfig, matches: &clap::ArgMatches<'_>) -> Option<String> {
+    if let Some(idf_id) = matches.value_of("idf-id") {
+        return config.get_installation(idf_id).map(|_| idf_id.to_string());
+    }
+    if let Some(idf_path) = matches.value_of("idf-path") {
+        return find_idf_id_by_path(config, idf_path);
+    }
+    if let Some(name) = matches.value_of("name") {
+        return config.find_id_by_name(name);
+    }
+    find_idf_id(config, matches.value_of("installation")?)
+}
+
+/// Resolve ESP-IDF installation used by a command from its --name or --idf-path argument,
+/// project pin file .idf-env in the current directory or its parents, or the selected installation.
+pub fn resolve_active_idf(matches: Option<&clap::ArgMatches<'_>>) -> ResolvedIdf {
+    let current_directory = env::current_dir().unwrap_or_default();
+    match pin::resolve_idf(&load_config_or_exit(), matches, &current_directory) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Path to ESP-IDF used by a command, see resolve_active_idf.
+pub fn get_idf_path_from_matches(matches: &clap::ArgMatches<'_>) -> String {
+    resolve_active_idf(Some(matches)).path
+}
+
+/// Directory of Python virtual environment which contains given Python binary, if it is located under python_env.
+fn find_python_env_dir(python_path: &str) -> Option<PathBuf> {
+    let python_env_root = PathBuf::from(format!("{}/python_env", get_tools_path()));
+    Path::new(python_path).ancestors()
+        .find(|ancestor| ancestor.parent() == Some(python_env_root.as_path()))
+        .map(|ancestor| ancestor.to_path_buf())
+}
+
+fn get_existence_flag(path: &str) -> &str {
+    if !path.is_empty() && Path::new(path).exists() {
+        "exists"
+    } else {
+        "missing"
+    }
+}
 
-    let mut parsed_json = load_json();
-    parsed_json["idfInstalled"].insert(&idf_id, _data).unwrap();
-    parsed_json["idfSelectedId"] = JsonValue::String(idf_id);
+fn list_idf_config() {
+    let config = load_config_or_exit();
+    if config.idf_installed.is_empty() {
+        println!("No ESP-IDF installation registered.");
+        return;
+    }
+    for (idf_id, installation) in &config.idf_installed {
+        let marker = if *idf_id == config.idf_selected_id { "*" } else { " " };
+        if installation.name.is_empty() {
+            println!("{} {}", marker, idf_id);
+        } else {
+            println!("{} {} ({})", marker, idf_id, installation.name);
+        }
+        println!("    version: {}", installation.version);
+        println!("    path:    {} ({})", installation.path, get_existence_flag(&installation.path));
+        println!("    python:  {} ({})", installation.python, get_existence_flag(&installation.python));
+    }
+}
+
+fn select_idf_config(idf_id: String) -> Result<()> {
+    update_config(|config| config.idf_selected_id = idf_id.clone())?;
+    println!("Selected ESP-IDF installation: {}", idf_id);
+    Ok(())
+}
 
-    fs::write(get_json_path(), format!("{:#}", parsed_json)).unwrap();
+fn remove_directory(directory: &Path) {
+    if directory.exists() {
+        println!("Removing: {}", directory.display());
+        match fs::remove_dir_all(directory) {
+            Ok(_) => { println!("Removed."); },
+            Err(_e) => { println!("Failed to remove."); }
+        }
+    }
+}
+
+fn remove_idf_config(idf_id: String, delete_files: bool) -> Result<()> {
+    let origin = load_layered_config()?.get_installation_origin(&idf_id);
+    if origin.layer != layers::Layer::User {
+        return Err(format!("Installation {} is defined by {} and it can't be removed by idf-env.", idf_id, origin).into());
+    }
+    let removed = update_config(|config| {
+        if config.idf_selected_id == idf_id {
+            println!("Removed installation was selected, no ESP-IDF installation is selected now.");
+            config.idf_selected_id = "".to_string();
+        }
+        config.idf_installed.remove(&idf_id)
+    })?;
+    println!("Removed ESP-IDF installation from configuration: {}", idf_id);
+
+    if let (Some(installation), true) = (removed, delete_files) {
+        if let Some(python_env_dir) = find_python_env_dir(&installation.python) {
+            remove_directory(&python_env_dir);
+        }
+        remove_directory(Path::new(&installation.path));
+    }
+    Ok(())
 }
 
 pub fn get_cmd<'a>() -> Command<'a, str> {
@@ -152,22 +393,36 @@ pub fn get_cmd<'a>() -> Command<'a, str> {
                         .help("ESP-IDF installation ID")
                         .takes_value(true),
                 )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .help("Custom name of ESP-IDF installation")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("show-source")
+                        .short("s")
+                        .long("show-source")
+                        .help("Report configuration layer which provided the value")
+                )
         })
         .runner(|_args, matches| {
             if matches.is_present("property") {
                 let property_name = matches.value_of("property").unwrap().to_string();
 
-                if matches.is_present("idf-id") {
-                    let idf_id = matches.value_of("idf-id").unwrap().to_string();
-                    print_property_with_id(property_name, idf_id);
-                } else if matches.is_present("idf-path") {
-                    let idf_path = matches.value_of("idf-path").unwrap().to_string();
-                    print_property_with_path(property_name, idf_path);
+                if matches.is_present("idf-id") || matches.is_present("idf-path") || matches.is_present("name") {
+                    match resolve_idf_id(&load_config_or_exit(), matches) {
+                        Some(idf_id) => print_property_with_id(property_name, idf_id, matches.is_present("show-source")),
+                        None => println!("ESP-IDF installation not found.")
+                    }
                 } else {
-                    print_property(property_name);
+                    print_property(property_name, matches.is_present("show-source"));
                 }
+            } else if matches.is_present("show-source") {
+                print_layers();
             } else {
-                let content = load_json();
+                let content = load_config_or_exit().to_json();
                 println!("{:#}", &content);
             }
             Ok(())
@@ -236,14 +491,121 @@ pub fn get_add_cmd<'a>() -> Command<'a, str> {
             let python_path = matches.value_of("python").unwrap().to_string();
             let version = matches.value_of("idf-version").unwrap().to_string();
             let idf_path = matches.value_of("idf-path").unwrap().to_string();
-            add_idf_config(idf_path, version, python_path);
+            let name = matches.value_of("name").unwrap_or("").to_string();
+            exit_on_error(add_idf_config(idf_path, version, python_path, name, true));
             Ok(())
         })
 }
 
+/// Check new value of property before it's stored. Without force only known properties
+/// are accepted and paths must exist. Name collisions and configVersion are rejected always.
+fn validate_property(config: &EspIdfConfig, idf_id: Option<&str>, property_name: &str, value: &str, force: bool) -> Result<()> {
+    if property_name == "configVersion" {
+        return Err("Property configVersion is managed by idf-env and it can't be changed.".into());
+    }
+    if let (Some(idf_id), "name") = (idf_id, property_name) {
+        if let Some(other_id) = config.find_id_by_name(value) {
+            if other_id != idf_id {
+                return Err(format!("Name '{}' is already used by installation {}", value, other_id).into());
+            }
+        }
+    }
+    if force {
+        return Ok(());
+    }
+
+    let known_properties = match idf_id {
+        Some(_) => IdfInstallation::PROPERTIES,
+        None => EspIdfConfig::PROPERTIES
+    };
+    if !known_properties.contains(&property_name) {
+        return Err(format!("Unknown property '{}'. Known properties: {}. Use --force to store it anyway.",
+                           property_name, known_properties.join(", ")).into());
+    }
+
+    // Empty value clears top-level property, also when it's set by system or project layer
+    if idf_id.is_none() && value.is_empty() {
+        return Ok(());
+    }
+
+    match property_name {
+        "gitPath" | "idfToolsPath" | "offlineBundlePath" | "path" | "python" => {
+            if !Path::new(value).exists() {
+                return Err(format!("Path {} does not exist. Use --force to store it anyway.", value).into());
+            }
+        },
+        "idfSelectedId" => {
+            if !config.idf_installed.contains_key(value) {
+                return Err(format!("ESP-IDF installation {} is not registered. Use: idf-env config list", value).into());
+            }
+        },
+        "version" => {
+            if value.is_empty() {
+                return Err("Version must not be empty.".into());
+            }
+        },
+        "offline" => {
+            if value != "true" && value != "false" {
+                return Err("Value of offline must be true or false.".into());
+            }
+        },
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Set top-level property or property of installation identified by idf_id.
+fn set_property(idf_id: Option<String>, property_name: String, value: String, force: bool) -> Result<()> {
+    let origin = match &idf_id {
+        Some(idf_id) => load_layered_config()?.get_installation_origin(idf_id),
+        None => load_layered_config()?.get_origin(&property_name),
+    };
+    if origin.layer > layers::Layer::User {
+        println!("Note: value stored in {} is overridden by {}", get_json_path(), origin);
+    }
+    update_config(|config| -> Result<()> {
+        validate_property(config, idf_id.as_deref(), &property_name, &value, force)?;
+        match &idf_id {
+            Some(idf_id) => {
+                match config.idf_installed.get_mut(idf_id) {
+                    Some(installation) => installation.set_property(&property_name, value.clone()),
+                    None => return Err(format!("ESP-IDF installation not found: {}", idf_id).into())
+                }
+            },
+            None => config.set_property(&property_name, value.clone())
+        }
+        Ok(())
+    })?
+}
+
 fn get_set_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
-    let git_path = matches.value_of("git").unwrap().to_string();
-    update_property("gitPath".to_string(), git_path);
+    let force = matches.is_present("force");
+
+    // Backward compatible form: config set --git <path>
+    if let Some(git_path) = matches.value_of("git") {
+        exit_on_error(set_property(None, "gitPath".to_string(), git_path.to_string(), force));
+        return Ok(());
+    }
+
+    let (property_name, value) = match (matches.value_of("property"), matches.value_of("value")) {
+        (Some(property_name), Some(value)) => (property_name.to_string(), value.to_string()),
+        _ => {
+            println!("Specify --property and --value, or --git.");
+            std::process::exit(1);
+        }
+    };
+
+    let mut idf_id = None;
+    if matches.is_present("idf-id") || matches.is_present("idf-path") || matches.is_present("name") || matches.is_present("installation") {
+        match resolve_idf_id(&load_config_or_exit(), matches) {
+            Some(resolved_id) => idf_id = Some(resolved_id),
+            None => {
+                println!("ESP-IDF installation not found. Use: idf-env config list");
+                std::process::exit(1);
+            }
+        }
+    }
+    exit_on_error(set_property(idf_id, property_name, value, force));
     Ok(())
 }
 
@@ -251,19 +613,125 @@ pub fn get_set_cmd<'a>() -> Command<'a, str> {
     Command::new("set")
         .description("set configuration")
         .options(|app| {
-                app.arg(
+            add_installation_args(app)
+                .arg(
                     Arg::with_name("git")
                         .short("g")
                         .long("git")
                         .help("Full path to Git binary")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("property")
+                        .short("p")
+                        .long("property")
+                        .help("Name of property, e.g. gitPath or python when used with installation")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("value")
+                        .short("v")
+                        .long("value")
+                        .help("New value of the property")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Store the value without validation")
+                )
         })
         .runner(|_args, matches|
             get_set_runner(_args, matches)
         )
 }
 
+fn add_installation_args<'a, 'b>(app: clap::App<'a, 'b>) -> clap::App<'a, 'b> {
+    app.arg(
+        Arg::with_name("installation")
+            .help("ID, name or path of ESP-IDF installation")
+            .index(1)
+    )
+        .arg(
+            Arg::with_name("idf-path")
+                .short("i")
+                .long("idf-path")
+                .help("Path to ESP-IDF")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("idf-id")
+                .short("j")
+                .long("idf-id")
+                .help("ESP-IDF installation ID")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .help("Custom name of ESP-IDF installation")
+                .takes_value(true),
+        )
+}
+
+fn get_select_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    match resolve_idf_id(&load_config_or_exit(), matches) {
+        Some(idf_id) => exit_on_error(select_idf_config(idf_id)),
+        None => {
+            println!("ESP-IDF installation not found. Use: idf-env config list");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+fn get_rm_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    match resolve_idf_id(&load_config_or_exit(), matches) {
+        Some(idf_id) => exit_on_error(remove_idf_config(idf_id, matches.is_present("delete-files"))),
+        None => {
+            println!("ESP-IDF installation not found. Use: idf-env config list");
+            std::process::exit(1);
+        }
+    }
+    Ok(())
+}
+
+pub fn get_list_cmd<'a>() -> Command<'a, str> {
+    Command::new("list")
+        .description("List registered ESP-IDF installations")
+        .runner(|_args, _matches| {
+            list_idf_config();
+            Ok(())
+        })
+}
+
+pub fn get_select_cmd<'a>() -> Command<'a, str> {
+    Command::new("select")
+        .description("Select active ESP-IDF installation")
+        .options(|app| add_installation_args(app))
+        .runner(|_args, matches|
+            get_select_runner(_args, matches)
+        )
+}
+
+pub fn get_rm_cmd<'a>() -> Command<'a, str> {
+    Command::new("rm")
+        .description("Remove ESP-IDF installation from configuration")
+        .options(|app| {
+            add_installation_args(app)
+                .arg(
+                    Arg::with_name("delete-files")
+                        .short("d")
+                        .long("delete-files")
+                        .help("Delete ESP-IDF directory and its Python environment from disk")
+                )
+        })
+        .runner(|_args, matches|
+            get_rm_runner(_args, matches)
+        )
+}
 
 pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
     let multi_cmd: MultiCommand<str, str> = Commander::new()
@@ -271,6 +739,14 @@ pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
         .add_cmd(get_edit_cmd())
         .add_cmd(get_add_cmd())
         .add_cmd(get_set_cmd())
+        .add_cmd(get_list_cmd())
+        .add_cmd(get_select_cmd())
+        .add_cmd(get_rm_cmd())
+        .add_cmd(doctor::get_doctor_cmd())
+        .add_cmd(discover::get_discover_cmd())
+        .add_cmd(portable::get_export_cmd())
+        .add_cmd(portable::get_import_cmd())
+        .add_cmd(pin::get_which_cmd())
         .into_cmd("config")
 
         // Optionally specify a description
@@ -278,3 +754,15 @@ pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
 
     return multi_cmd;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_get_version_major_minor() {
+        assert_eq!(get_version_major_minor("Python 3.8.7"), "3.8");
+        assert_eq!(get_version_major_minor("v4.4.2"), "4.4");
+        assert_eq!(get_version_major_minor("v5.1-dev-1234-gabcdef"), "5.1");
+    }
+}
@@ -0,0 +1,190 @@
+use clap::Arg;
+use clap_nested::Command;
+use git2::{DescribeOptions, Repository};
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+use crate::config::{add_idf_config, find_idf_id, get_tools_path, get_version_major_minor, load_config_or_exit};
+use crate::idf::get_idf_base_directory;
+use crate::package::offline::is_dry_run;
+
+/// ESP-IDF checkout found on disk.
+struct DiscoveredIdf {
+    path: String,
+    version: String,
+    python: String,
+}
+
+fn is_idf_directory(path: &Path) -> bool {
+    path.join("tools").join("idf_tools.py").is_file()
+}
+
+/// Version from version.txt distributed in release archives, otherwise from git describe.
+fn get_idf_version(idf_path: &Path) -> String {
+    if let Ok(content) = fs::read_to_string(idf_path.join("version.txt")) {
+        let version = content.trim();
+        if !version.is_empty() {
+            return version.to_string();
+        }
+    }
+
+    if let Ok(repo) = Repository::open(idf_path) {
+        let mut options = DescribeOptions::new();
+        options.describe_tags();
+        if let Ok(description) = repo.describe(&options) {
+            if let Ok(version) = description.format(None) {
+                return version;
+            }
+        }
+    }
+    "unknown".to_string()
+}
+
+#[cfg(windows)]
+fn get_python_in_env(python_env: &Path) -> PathBuf {
+    python_env.join("Scripts").join("python.exe")
+}
+
+#[cfg(unix)]
+fn get_python_in_env(python_env: &Path) -> PathBuf {
+    python_env.join("bin").join("python")
+}
+
+/// Find Python of virtual environment created by idf_tools.py for given ESP-IDF version,
+/// e.g. python_env/idf5.1_py3.11_env. The newest Python wins when there are more of them.
+fn find_python_env(idf_version: &str) -> Option<String> {
+    let prefix = format!("idf{}_py", get_version_major_minor(idf_version));
+    let python_env_root = format!("{}/python_env", get_tools_path());
+    let mut candidates: Vec<PathBuf> = fs::read_dir(python_env_root).ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| {
+            let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+            file_name.starts_with(&prefix) && file_name.ends_with("_env")
+        })
+        .map(|path| get_python_in_env(&path))
+        .filter(|python| python.exists())
+        .collect();
+    candidates.sort();
+    candidates.pop().map(|python| python.display().to_string())
+}
+
+fn discover_in_root(root: &str, max_depth: usize) -> Vec<DiscoveredIdf> {
+    let mut result = Vec::new();
+    let mut walker = WalkDir::new(root).max_depth(max_depth).into_iter();
+    while let Some(entry) = walker.next() {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(_e) => continue,
+        };
+        if !entry.file_type().is_dir() || !is_idf_directory(entry.path()) {
+            continue;
+        }
+        // Do not descend into ESP-IDF, it may contain nested copies e.g. in examples
+        walker.skip_current_dir();
+
+        let version = get_idf_version(entry.path());
+        let python = find_python_env(&version).unwrap_or_default();
+        result.push(DiscoveredIdf {
+            path: entry.path().display().to_string().replace("\\", "/"),
+            version,
+            python,
+        });
+    }
+    result
+}
+
+fn get_discover_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let roots: Vec<String> = match matches.values_of("root") {
+        Some(values) => values.map(|value| value.to_string()).collect(),
+        None => vec![get_idf_base_directory()],
+    };
+    let max_depth: usize = matches.value_of("depth").unwrap().parse().unwrap_or(2);
+    let dry_run = matches.is_present("dry-run") || is_dry_run();
+
+    let mut registered = 0;
+    for root in roots {
+        println!("Scanning: {}", root);
+        for idf in discover_in_root(&root, max_depth) {
+            let python = if idf.python.is_empty() { "no Python environment found" } else { idf.python.as_str() };
+            // Roots may overlap, reload configuration to see installations registered by previous iteration
+            if let Some(idf_id) = find_idf_id(&load_config_or_exit(), &idf.path) {
+                println!("* {} ({}) - already registered as {}", idf.path, idf.version, idf_id);
+                continue;
+            }
+            println!("* {} ({}) - {}", idf.path, idf.version, python);
+            if dry_run {
+                continue;
+            }
+            match add_idf_config(idf.path.clone(), idf.version, idf.python, "".to_string(), matches.is_present("select")) {
+                Ok(_) => registered += 1,
+                Err(e) => println!("Unable to register {}: {}", idf.path, e),
+            }
+        }
+    }
+    println!("Registered {} installation(s).", registered);
+    Ok(())
+}
+
+pub fn get_discover_cmd<'a>() -> Command<'a, str> {
+    Command::new("discover")
+        .description("Find existing ESP-IDF checkouts and register them")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("root")
+                    .short("r")
+                    .long("root")
+                    .help("Directory to scan, can be used multiple times. Default: C:/esp or ~/esp")
+                    .takes_value(true)
+                    .multiple(true)
+                    .number_of_values(1)
+            )
+                .arg(
+                    Arg::with_name("depth")
+                        .short("d")
+                        .long("depth")
+                        .help("Maximal depth of directories scanned under the root")
+                        .takes_value(true)
+                        .default_value("2")
+                )
+                .arg(
+                    Arg::with_name("select")
+                        .short("s")
+                        .long("select")
+                        .help("Select discovered installation, the last one when more are found")
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .short("n")
+                        .long("dry-run")
+                        .help("Only print discovered installations, same as global --dry-run")
+                )
+        })
+        .runner(|_args, matches|
+            get_discover_runner(_args, matches)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_discover_in_root() {
+        let root = std::env::temp_dir().join(format!("idf-env-discover-{}", std::process::id()));
+        let idf_path = root.join("v5.1");
+        fs::create_dir_all(idf_path.join("tools")).unwrap();
+        fs::create_dir_all(idf_path.join("examples/nested/tools")).unwrap();
+        fs::create_dir_all(root.join("not-idf")).unwrap();
+        fs::write(idf_path.join("tools/idf_tools.py"), "").unwrap();
+        fs::write(idf_path.join("examples/nested/tools/idf_tools.py"), "").unwrap();
+        fs::write(idf_path.join("version.txt"), "v5.1.2\n").unwrap();
+
+        let discovered = discover_in_root(&root.display().to_string(), 4);
+        assert_eq!(discovered.len(), 1);
+        assert_eq!(discovered[0].version, "v5.1.2");
+        assert!(discovered[0].path.ends_with("/v5.1"));
+        fs::remove_dir_all(root).unwrap();
+    }
+}
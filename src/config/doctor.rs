@@ -0,0 +1,226 @@
+use clap::Arg;
+use clap_nested::Command;
+use git2::Repository;
+use json::JsonValue;
+use std::path::Path;
+use std::process::Stdio;
+
+use crate::config::layers::{Layer, LayeredConfig};
+use crate::config::{get_python_env_path, get_version_major_minor, load_layered_config, update_config, EspIdfConfig, IdfInstallation};
+
+struct Check {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+impl Check {
+    fn new(name: &'static str, passed: bool, detail: String) -> Check {
+        Check { name, passed, detail }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        json::object! {
+            "name": self.name,
+            "passed": self.passed,
+            "detail": self.detail.clone()
+        }
+    }
+}
+
+/// Result of validation of one entry: top-level gitPath or one of idfInstalled records.
+struct EntryReport {
+    id: String,
+    checks: Vec<Check>,
+    /// Entry points to data which does not exist anymore and can be pruned by --fix.
+    dead: bool,
+}
+
+impl EntryReport {
+    fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.passed)
+    }
+
+    fn to_json(&self) -> JsonValue {
+        json::object! {
+            "id": self.id.clone(),
+            "passed": self.passed(),
+            "dead": self.dead,
+            "checks": JsonValue::Array(self.checks.iter().map(|check| check.to_json()).collect())
+        }
+    }
+
+    fn print(&self) {
+        println!("{}: {}", self.id, if self.passed() { "PASS" } else { "FAIL" });
+        for check in &self.checks {
+            println!("    [{}] {}: {}", if check.passed { "PASS" } else { "FAIL" }, check.name, check.detail);
+        }
+    }
+}
+
+/// Run binary with --version and return the first line of its output.
+fn get_binary_version(binary_path: &str) -> Option<String> {
+    let output = std::process::Command::new(binary_path)
+        .arg("--version")
+        .stdin(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    // Python 2 reports version on stderr
+    let mut text = String::from_utf8_lossy(&output.stdout).to_string();
+    if text.trim().is_empty() {
+        text = String::from_utf8_lossy(&output.stderr).to_string();
+    }
+    text.lines().next().map(|line| line.trim().to_string())
+}
+
+fn check_git(git_path: &str) -> EntryReport {
+    let mut checks = Vec::new();
+    if git_path.is_empty() {
+        checks.push(Check::new("git binary", false, "gitPath is not set".to_string()));
+    } else {
+        match get_binary_version(git_path) {
+            Some(version) => checks.push(Check::new("git binary", true, version)),
+            None => checks.push(Check::new("git binary", false, format!("unable to run {}", git_path))),
+        }
+    }
+    EntryReport { id: "gitPath".to_string(), checks, dead: false }
+}
+
+fn check_installation(idf_id: &str, installation: &IdfInstallation) -> EntryReport {
+    let mut checks = Vec::new();
+    let idf_path = Path::new(&installation.path);
+    let path_exists = !installation.path.is_empty() && idf_path.is_dir();
+    checks.push(Check::new("path exists", path_exists, installation.path.clone()));
+
+    if path_exists {
+        match Repository::open(idf_path) {
+            Ok(_) => checks.push(Check::new("git repository", true, "ok".to_string())),
+            Err(e) => checks.push(Check::new("git repository", false, e.message().to_string())),
+        }
+        let idf_tools = idf_path.join("tools").join("idf_tools.py");
+        checks.push(Check::new("tools/idf_tools.py", idf_tools.exists(), idf_tools.display().to_string()));
+    }
+
+    match get_binary_version(&installation.python) {
+        Some(python_version) => {
+            let python_env_path = get_python_env_path(get_version_major_minor(&installation.version), get_version_major_minor(&python_version));
+            checks.push(Check::new("python binary", true, python_version));
+            checks.push(Check::new("python environment", Path::new(&python_env_path).exists(), python_env_path));
+        },
+        None => checks.push(Check::new("python binary", false, format!("unable to run {}", installation.python))),
+    }
+
+    EntryReport { id: idf_id.to_string(), checks, dead: !path_exists }
+}
+
+fn check_config(config: &EspIdfConfig) -> Vec<EntryReport> {
+    let mut reports = vec![check_git(&config.git_path)];
+    for (idf_id, installation) in &config.idf_installed {
+        reports.push(check_installation(idf_id, installation));
+    }
+    reports
+}
+
+/// Remove dead entries from the user layer. Entries defined by system or project file are only reported
+/// with the file which must be edited, they would come back on the next run otherwise.
+fn prune_dead_entries(reports: &[EntryReport], layered: &LayeredConfig) {
+    let mut dead_ids: Vec<String> = Vec::new();
+    for report in reports.iter().filter(|report| report.dead) {
+        let origin = layered.get_installation_origin(&report.id);
+        if origin.layer == Layer::User {
+            dead_ids.push(report.id.clone());
+        } else {
+            println!("Not pruned: {} is defined by {} layer, remove it from {}", report.id, origin.layer, origin.location);
+        }
+    }
+    if dead_ids.is_empty() {
+        return;
+    }
+
+    let result = update_config(|config| {
+        let mut pruned_ids = Vec::new();
+        for idf_id in &dead_ids {
+            if config.idf_installed.remove(idf_id).is_some() {
+                pruned_ids.push(idf_id.clone());
+            }
+            if config.idf_selected_id == *idf_id {
+                config.idf_selected_id = "".to_string();
+            }
+        }
+        pruned_ids
+    });
+    match result {
+        Ok(pruned_ids) => {
+            for idf_id in &pruned_ids {
+                println!("Pruned: {}", idf_id);
+            }
+        },
+        Err(e) => println!("Unable to prune entries: {}", e)
+    }
+}
+
+fn get_doctor_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let layered = match load_layered_config() {
+        Ok(layered) => layered,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let reports = check_config(&layered.config);
+
+    if matches.is_present("json") {
+        let document = JsonValue::Array(reports.iter().map(|report| report.to_json()).collect());
+        println!("{:#}", document);
+    } else {
+        for report in &reports {
+            report.print();
+        }
+    }
+
+    if matches.is_present("fix") {
+        prune_dead_entries(&reports, &layered);
+    } else if !reports.iter().all(|report| report.passed()) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn get_doctor_cmd<'a>() -> Command<'a, str> {
+    Command::new("doctor")
+        .description("Validate registered ESP-IDF installations")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("json")
+                    .short("j")
+                    .long("json")
+                    .help("Print report in JSON format")
+            )
+                .arg(
+                    Arg::with_name("fix")
+                        .short("f")
+                        .long("fix")
+                        .help("Remove installations which do not exist on disk anymore from the user configuration")
+                )
+        })
+        .runner(|_args, matches|
+            get_doctor_runner(_args, matches)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_missing_installation_is_dead() {
+        let installation = IdfInstallation::new("/nonexistent/esp-idf".to_string(), "5.1".to_string(), "/nonexistent/python".to_string());
+        let report = check_installation("esp-idf-1", &installation);
+        assert!(report.dead);
+        assert!(!report.passed());
+        assert_eq!(report.checks.len(), 2);
+    }
+}
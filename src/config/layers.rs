@@ -0,0 +1,256 @@
+use json::JsonValue;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{EspIdfConfig, Result};
+
+/// Name of project configuration file searched in the current directory and its parents. It has the format
+/// of esp_idf.json and it is unrelated to the pin file .idf-env, which only selects ESP-IDF installation.
+pub const PROJECT_CONFIG_NAME: &str = "esp_idf.project.json";
+
+/// Prefix of environment variables which override configuration, e.g. IDF_ENV_GIT_PATH for gitPath.
+const ENVIRONMENT_PREFIX: &str = "IDF_ENV_";
+
+/// Layers of configuration ordered from the lowest to the highest priority.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Layer {
+    Default,
+    System,
+    User,
+    Project,
+    Environment,
+}
+
+impl fmt::Display for Layer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Layer::Default => "default",
+            Layer::System => "system",
+            Layer::User => "user",
+            Layer::Project => "project",
+            Layer::Environment => "environment",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Layer and the file or variable which provided a value.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Origin {
+    pub layer: Layer,
+    pub location: String,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.layer, self.location)
+    }
+}
+
+/// Effective configuration merged from all layers with origin of each property.
+pub struct LayeredConfig {
+    pub config: EspIdfConfig,
+    origins: BTreeMap<String, Origin>,
+}
+
+impl LayeredConfig {
+    /// Origin of top-level property.
+    pub fn get_origin(&self, property_name: &str) -> Origin {
+        self.origins.get(property_name).cloned().unwrap_or(Origin {
+            layer: Layer::Default,
+            location: "built-in".to_string(),
+        })
+    }
+
+    /// Origin of installation record, installation properties are not merged across layers.
+    pub fn get_installation_origin(&self, idf_id: &str) -> Origin {
+        self.get_origin(&format!("idfInstalled/{}", idf_id))
+    }
+
+    fn apply(&mut self, layer: Layer, location: &str, document: &JsonValue) {
+        for key in self.config.overlay(document) {
+            self.origins.insert(key, Origin { layer, location: location.to_string() });
+        }
+    }
+}
+
+/// Read-only configuration shared by all users of the machine, IDF_ENV_SYSTEM_CONFIG points to another file.
+#[cfg(windows)]
+pub fn get_system_config_path() -> String {
+    env::var("IDF_ENV_SYSTEM_CONFIG").unwrap_or_else(|_e| {
+        let program_data = env::var("ProgramData").unwrap_or_else(|_e| "C:/ProgramData".to_string());
+        format!("{}/Espressif/idf-env/esp_idf.json", program_data.replace("\\", "/"))
+    })
+}
+
+/// Read-only configuration shared by all users of the machine, IDF_ENV_SYSTEM_CONFIG points to another file.
+#[cfg(unix)]
+pub fn get_system_config_path() -> String {
+    env::var("IDF_ENV_SYSTEM_CONFIG").unwrap_or_else(|_e| "/etc/idf-env/esp_idf.json".to_string())
+}
+
+/// Find the nearest file with given name in the directory or its parents.
+pub fn find_in_ancestors(start_directory: &Path, file_name: &str) -> Option<PathBuf> {
+    start_directory.ancestors()
+        .map(|directory| directory.join(file_name))
+        .find(|path| path.is_file())
+}
+
+pub fn find_project_config() -> Option<PathBuf> {
+    find_in_ancestors(&env::current_dir().ok()?, PROJECT_CONFIG_NAME)
+}
+
+/// Read optional layer file, missing file is an empty layer.
+fn read_layer(path: &Path) -> Result<Option<JsonValue>> {
+    if !path.is_file() {
+        return Ok(None);
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    let document = json::parse(&content)
+        .map_err(|e| format!("Configuration file {} is corrupted: {}", path.display(), e))?;
+    if !document.is_object() {
+        return Err(format!("Configuration file {} must contain JSON object", path.display()).into());
+    }
+    Ok(Some(document))
+}
+
+/// Transform property name to environment variable, e.g. gitPath to IDF_ENV_GIT_PATH.
+fn get_environment_variable_name(property_name: &str) -> String {
+    let mut variable_name = ENVIRONMENT_PREFIX.to_string();
+    for character in property_name.chars() {
+        if character.is_ascii_uppercase() {
+            variable_name.push('_');
+        }
+        variable_name.push(character.to_ascii_uppercase());
+    }
+    variable_name
+}
+
+/// Environment variables which override top-level properties.
+pub fn get_environment_variable_names() -> Vec<String> {
+    EspIdfConfig::PROPERTIES.iter()
+        .filter(|name| **name != "configVersion")
+        .map(|name| get_environment_variable_name(name))
+        .collect()
+}
+
+/// Value of idfToolsPath defined by environment, project or system layer. The user layer
+/// is stored in the tools directory, so it can't relocate the directory itself.
+/// Corrupted layer file is an error like in merge_layers, it must not silently change the directory.
+pub fn get_layered_tools_path() -> Result<Option<String>> {
+    if let Ok(tools_path) = env::var(get_environment_variable_name("idfToolsPath")) {
+        return Ok(Some(tools_path));
+    }
+    let layer_paths: Vec<PathBuf> = vec![find_project_config(), Some(PathBuf::from(get_system_config_path()))]
+        .into_iter().flatten().collect();
+    find_tools_path(&layer_paths)
+}
+
+/// The first non-empty idfToolsPath of layer files in the order of decreasing priority.
+fn find_tools_path(layer_paths: &[PathBuf]) -> Result<Option<String>> {
+    for layer_path in layer_paths {
+        if let Some(document) = read_layer(layer_path)? {
+            if let Some(tools_path) = document["idfToolsPath"].as_str().filter(|path| !path.is_empty()) {
+                return Ok(Some(tools_path.to_string()));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Merge system file, user configuration, project file and environment overrides, in this order.
+pub fn merge_layers(user_config: &EspIdfConfig, user_path: &str, tools_path: String) -> Result<LayeredConfig> {
+    let mut layered = LayeredConfig { config: EspIdfConfig::new(tools_path), origins: BTreeMap::new() };
+
+    let system_path = get_system_config_path();
+    if let Some(document) = read_layer(Path::new(&system_path))? {
+        layered.apply(Layer::System, &system_path, &document);
+    }
+
+    layered.apply(Layer::User, user_path, &user_config.to_json());
+    for (key, value) in &user_config.extra {
+        layered.config.extra.insert(key.clone(), value.clone());
+    }
+
+    if let Some(project_path) = find_project_config() {
+        if let Some(document) = read_layer(&project_path)? {
+            layered.apply(Layer::Project, &project_path.display().to_string(), &document);
+        }
+    }
+
+    for property_name in EspIdfConfig::PROPERTIES.iter().filter(|name| **name != "configVersion") {
+        let variable_name = get_environment_variable_name(property_name);
+        if let Ok(value) = env::var(&variable_name) {
+            // Variable set to empty value clears the property
+            let mut document = JsonValue::new_object();
+            document[*property_name] = if value.is_empty() { JsonValue::Null } else { value.into() };
+            layered.apply(Layer::Environment, &variable_name, &document);
+        }
+    }
+    Ok(layered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IdfInstallation;
+
+    #[test]
+    fn test_get_environment_variable_name() {
+        assert_eq!(get_environment_variable_name("gitPath"), "IDF_ENV_GIT_PATH");
+        assert_eq!(get_environment_variable_name("idfToolsPath"), "IDF_ENV_IDF_TOOLS_PATH");
+    }
+
+    #[test]
+    fn test_find_tools_path() {
+        let directory = std::env::temp_dir().join(format!("idf-env-layers-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let (project, system) = (directory.join("esp_idf.project.json"), directory.join("esp_idf.json"));
+        fs::write(&system, r#"{"idfToolsPath": "/opt/espressif"}"#).unwrap();
+        assert_eq!(find_tools_path(&[project.clone(), system.clone()]).unwrap(), Some("/opt/espressif".to_string()));
+
+        // Corrupted project layer is reported, system layer is not used instead
+        fs::write(&project, "{").unwrap();
+        assert!(find_tools_path(&[project, system]).unwrap_err().to_string().contains("corrupted"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_overlay_reports_origin() {
+        let mut layered = LayeredConfig { config: EspIdfConfig::new("/tools".to_string()), origins: BTreeMap::new() };
+        let system = json::object! {
+            "gitPath": "/usr/bin/git",
+            "idfSelectedId": "esp-idf-1",
+            "idfInstalled": { "esp-idf-1": { "version": "v4.4", "python": "/usr/bin/python3", "path": "/opt/esp-idf" } }
+        };
+        layered.apply(Layer::System, "/etc/idf-env/esp_idf.json", &system);
+
+        let mut user = EspIdfConfig::new("/tools".to_string());
+        user.idf_installed.insert("esp-idf-2".to_string(), IdfInstallation::new("/home/esp".to_string(), "v5.1".to_string(), "".to_string()));
+        layered.apply(Layer::User, "/tools/esp_idf.json", &user.to_json());
+
+        assert_eq!(layered.config.git_path, "/usr/bin/git");
+        assert_eq!(layered.get_origin("gitPath").layer, Layer::System);
+        assert_eq!(layered.get_origin("idfToolsPath").layer, Layer::User);
+        assert_eq!(layered.config.idf_installed.len(), 2);
+        assert_eq!(layered.get_installation_origin("esp-idf-1").layer, Layer::System);
+        assert_eq!(layered.get_installation_origin("esp-idf-2").layer, Layer::User);
+        assert_eq!(layered.get_origin("unknownProperty").layer, Layer::Default);
+
+        // Empty string does not override lower layer, null clears it
+        layered.apply(Layer::Project, "esp_idf.project.json", &json::object! { "gitPath": "" });
+        assert_eq!(layered.config.git_path, "/usr/bin/git");
+        layered.apply(Layer::Project, "esp_idf.project.json", &json::object! { "gitPath": null });
+        assert_eq!(layered.config.git_path, "");
+        // User file keeps strings for other readers, the property is cleared by a separate key
+        user.set_property("idfSelectedId", "".to_string());
+        assert_eq!(user.to_json()["idfSelectedId"], "");
+        layered.apply(Layer::User, "/tools/esp_idf.json", &user.to_json());
+        assert_eq!(layered.config.idf_selected_id, "");
+        assert_eq!(layered.get_origin("idfSelectedId").layer, Layer::User);
+    }
+}
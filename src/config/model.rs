@@ -0,0 +1,382 @@
+use std::collections::{BTreeMap, BTreeSet};
+use json::JsonValue;
+
+/// Version of esp_idf.json format written by this build of idf-env.
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Upgrade steps of the configuration document. Entry N migrates version N to N + 1.
+const MIGRATIONS: &[fn(&mut JsonValue)] = &[
+    migrate_v0_to_v1,
+];
+
+/// Files created before versioning was introduced do not contain `configVersion`.
+/// Make sure that all top-level keys exist and drop malformed installation records.
+fn migrate_v0_to_v1(document: &mut JsonValue) {
+    for key in ["gitPath", "idfToolsPath", "idfSelectedId"] {
+        if !document[key].is_string() {
+            document[key] = JsonValue::String("".to_string());
+        }
+    }
+
+    if !document["idfInstalled"].is_object() {
+        document["idfInstalled"] = JsonValue::new_object();
+    }
+
+    let broken_ids: Vec<String> = document["idfInstalled"].entries()
+        .filter(|(_, entry)| !entry.is_object())
+        .map(|(id, _)| id.to_string())
+        .collect();
+    for idf_id in broken_ids {
+        eprintln!("Removing malformed installation record: {}", idf_id);
+        document["idfInstalled"].remove(&idf_id);
+    }
+}
+
+/// Bring a parsed esp_idf.json up to `CONFIG_VERSION`. Returns true when the document was changed.
+pub fn migrate(document: &mut JsonValue) -> Result<bool, String> {
+    let mut version = document["configVersion"].as_u32().unwrap_or(0);
+    if version > CONFIG_VERSION {
+        return Err(format!("Configuration version {} is newer than supported version {}. Please, update idf-env.",
+                           version, CONFIG_VERSION));
+    }
+
+    let migrated = version < CONFIG_VERSION;
+    while version < CONFIG_VERSION {
+        eprintln!("Migrating configuration from version {} to {}", version, version + 1);
+        MIGRATIONS[version as usize](document);
+        version += 1;
+    }
+    document["configVersion"] = version.into();
+    Ok(migrated)
+}
+
+fn take_string(object: &mut JsonValue, key: &str) -> String {
+    let value = object.remove(key);
+    value.as_str().unwrap_or("").to_string()
+}
+
+/// Record of one ESP-IDF installation stored under `idfInstalled`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct IdfInstallation {
+    /// Optional human readable name, e.g. v4.4 or master.
+    pub name: String,
+    pub version: String,
+    pub python: String,
+    pub path: String,
+    /// Properties written by other tools, e.g. Eclipse plugin, preserved on save.
+    pub extra: BTreeMap<String, JsonValue>,
+}
+
+impl IdfInstallation {
+    /// Properties of installation record known to idf-env.
+    pub const PROPERTIES: &'static [&'static str] = &["name", "version", "python", "path"];
+
+    pub fn new(path: String, version: String, python: String) -> IdfInstallation {
+        IdfInstallation { name: "".to_string(), version, python, path, extra: BTreeMap::new() }
+    }
+
+    fn from_json(mut value: JsonValue) -> IdfInstallation {
+        let name = take_string(&mut value, "name");
+        let version = take_string(&mut value, "version");
+        let python = take_string(&mut value, "python");
+        let path = take_string(&mut value, "path");
+        let extra = value.entries().map(|(k, v)| (k.to_string(), v.clone())).collect();
+        IdfInstallation { name, version, python, path, extra }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut value = json::object! {
+            version: self.version.clone(),
+            python: self.python.clone(),
+            path: self.path.clone()
+        };
+        if !self.name.is_empty() {
+            value["name"] = self.name.clone().into();
+        }
+        for (key, extra_value) in &self.extra {
+            value[key.as_str()] = extra_value.clone();
+        }
+        value
+    }
+
+    pub fn get_property(&self, property_name: &str) -> Option<String> {
+        match property_name {
+            "name" => Some(self.name.clone()),
+            "version" => Some(self.version.clone()),
+            "python" => Some(self.python.clone()),
+            "path" => Some(self.path.clone()),
+            _ => self.extra.get(property_name).map(|v| v.as_str().map(|s| s.to_string()).unwrap_or(v.dump())),
+        }
+    }
+
+    pub fn set_property(&mut self, property_name: &str, value: String) {
+        match property_name {
+            "name" => self.name = value,
+            "version" => self.version = value,
+            "python" => self.python = value,
+            "path" => self.path = value,
+            _ => { self.extra.insert(property_name.to_string(), value.into()); }
+        }
+    }
+}
+
+/// Typed content of esp_idf.json.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EspIdfConfig {
+    pub config_version: u32,
+    pub git_path: String,
+    pub idf_tools_path: String,
+    pub idf_selected_id: String,
+    pub idf_installed: BTreeMap<String, IdfInstallation>,
+    /// Top-level properties which are not part of the model, e.g. $schema.
+    pub extra: BTreeMap<String, JsonValue>,
+    /// Top-level properties set to empty value, which clears the value of lower layers, while empty string
+    /// alone means that the property is not set. They are stored in `clearedProperties`, because other readers
+    /// of esp_idf.json, e.g. Eclipse plugin, expect strings in the properties.
+    pub cleared: BTreeSet<String>,
+}
+
+/// Key of esp_idf.json with names of properties cleared by the file.
+pub const CLEARED_PROPERTIES_KEY: &str = "clearedProperties";
+
+impl EspIdfConfig {
+    /// Top-level properties known to idf-env.
+    pub const PROPERTIES: &'static [&'static str] = &["configVersion", "gitPath", "idfToolsPath", "idfSelectedId", "downloadMirrors", "offline", "offlineBundlePath", "signingKeys"];
+
+    pub fn new(tools_path: String) -> EspIdfConfig {
+        let mut extra = BTreeMap::new();
+        extra.insert("$schema".to_string(), "http://json-schema.org/schema#".into());
+        extra.insert("$id".to_string(), "http://dl.espressif.com/dl/schemas/esp_idf".into());
+        extra.insert("_comment".to_string(), "Configuration file for ESP-IDF Eclipse plugin.".into());
+        extra.insert("_warning".to_string(), "Use / or \\ when specifying path. Single backslash is not allowed by JSON format.".into());
+        EspIdfConfig {
+            config_version: CONFIG_VERSION,
+            idf_tools_path: tools_path,
+            extra,
+            ..Default::default()
+        }
+    }
+
+    /// Parse content of esp_idf.json, migrating older formats when necessary.
+    /// The flag in result reports whether the content had to be migrated.
+    pub fn parse_and_migrate(content: &str) -> Result<(EspIdfConfig, bool), String> {
+        let mut document = json::parse(content).map_err(|e| e.to_string())?;
+        if !document.is_object() {
+            return Err("Configuration root must be JSON object".to_string());
+        }
+        let migrated = migrate(&mut document)?;
+
+        let config_version = document.remove("configVersion").as_u32().unwrap_or(CONFIG_VERSION);
+        // Null written by earlier builds is read as cleared property too
+        let mut cleared: BTreeSet<String> = document.entries()
+            .filter(|(_, value)| value.is_null())
+            .map(|(key, _)| key.to_string())
+            .collect();
+        cleared.extend(document.remove(CLEARED_PROPERTIES_KEY).members().filter_map(|name| name.as_str()).map(|name| name.to_string()));
+        let git_path = take_string(&mut document, "gitPath");
+        let idf_tools_path = take_string(&mut document, "idfToolsPath");
+        let idf_selected_id = take_string(&mut document, "idfSelectedId");
+        let installed = document.remove("idfInstalled");
+        let idf_installed = installed.entries()
+            .map(|(id, entry)| (id.to_string(), IdfInstallation::from_json(entry.clone())))
+            .collect();
+        let extra = document.entries()
+            .filter(|(_, v)| !v.is_null())
+            .map(|(k, v)| (k.to_string(), v.clone()))
+            .collect();
+
+        Ok((EspIdfConfig { config_version, git_path, idf_tools_path, idf_selected_id, idf_installed, extra, cleared }, migrated))
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let mut document = JsonValue::new_object();
+        for (key, value) in &self.extra {
+            document[key.as_str()] = value.clone();
+        }
+        document["configVersion"] = self.config_version.into();
+        document["gitPath"] = self.git_path.clone().into();
+        document["idfToolsPath"] = self.idf_tools_path.clone().into();
+        document["idfSelectedId"] = self.idf_selected_id.clone().into();
+        let mut installed = JsonValue::new_object();
+        for (idf_id, installation) in &self.idf_installed {
+            installed[idf_id.as_str()] = installation.to_json();
+        }
+        document["idfInstalled"] = installed;
+        // Property assigned directly after it was cleared is not cleared anymore
+        let cleared: Vec<JsonValue> = self.cleared.iter()
+            .filter(|key| self.get_property(key).unwrap_or_default().is_empty())
+            .map(|key| key.as_str().into())
+            .collect();
+        if !cleared.is_empty() {
+            document[CLEARED_PROPERTIES_KEY] = JsonValue::Array(cleared);
+        }
+        document
+    }
+
+    pub fn get_property(&self, property_name: &str) -> Option<String> {
+        match property_name {
+            "configVersion" => Some(self.config_version.to_string()),
+            "gitPath" => Some(self.git_path.clone()),
+            "idfToolsPath" => Some(self.idf_tools_path.clone()),
+            "idfSelectedId" => Some(self.idf_selected_id.clone()),
+            _ => self.extra.get(property_name).map(|v| v.as_str().map(|s| s.to_string()).unwrap_or(v.dump())),
+        }
+    }
+
+    /// Set top-level property, configVersion is managed by migrations and it's not settable.
+    /// Empty value clears the property, also in lower configuration layers.
+    pub fn set_property(&mut self, property_name: &str, value: String) {
+        if property_name == "configVersion" {
+            return;
+        }
+        if value.is_empty() {
+            self.cleared.insert(property_name.to_string());
+            self.extra.remove(property_name);
+        } else {
+            self.cleared.remove(property_name);
+        }
+        match property_name {
+            "gitPath" => self.git_path = value,
+            "idfToolsPath" => self.idf_tools_path = value,
+            "idfSelectedId" => self.idf_selected_id = value,
+            _ if value.is_empty() => {},
+            _ => { self.extra.insert(property_name.to_string(), value.into()); }
+        }
+    }
+
+    /// Apply partial configuration document of another layer over this one.
+    /// Empty strings do not override lower layers, null and `clearedProperties` clear them. Returns names
+    /// of overridden properties, installations are reported as idfInstalled/<id>.
+    pub fn overlay(&mut self, document: &JsonValue) -> Vec<String> {
+        let mut applied = Vec::new();
+        for (key, value) in document.entries() {
+            if key == "idfInstalled" {
+                for (idf_id, entry) in value.entries().filter(|(_, entry)| entry.is_object()) {
+                    self.idf_installed.insert(idf_id.to_string(), IdfInstallation::from_json(entry.clone()));
+                    applied.push(format!("idfInstalled/{}", idf_id));
+                }
+                continue;
+            }
+            if key == CLEARED_PROPERTIES_KEY {
+                for name in value.members().filter_map(|name| name.as_str()).filter(|name| *name != "configVersion") {
+                    self.set_property(name, "".to_string());
+                    applied.push(name.to_string());
+                }
+                continue;
+            }
+            // Schema annotations and comments describe the file itself
+            if key == "configVersion" || key.starts_with('$') || key.starts_with('_') {
+                continue;
+            }
+            match value.as_str() {
+                Some("") => continue,
+                Some(text) => self.set_property(key, text.to_string()),
+                None if value.is_null() => self.set_property(key, "".to_string()),
+                None => { self.extra.insert(key.to_string(), value.clone()); }
+            }
+            applied.push(key.to_string());
+        }
+        applied
+    }
+
+    pub fn get_installation(&self, idf_id: &str) -> Option<&IdfInstallation> {
+        self.idf_installed.get(idf_id)
+    }
+
+    pub fn get_selected_installation(&self) -> Option<&IdfInstallation> {
+        self.get_installation(&self.idf_selected_id)
+    }
+
+    pub fn find_id_by_name(&self, name: &str) -> Option<String> {
+        if name.is_empty() {
+            return None;
+        }
+        self.idf_installed.iter()
+            .find(|(_, installation)| installation.name == name)
+            .map(|(idf_id, _)| idf_id.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_unversioned_config() {
+        let content = r#"{
+            "$schema": "http://json-schema.org/schema#",
+            "gitPath": "C:/git/git.exe",
+            "idfSelectedId": "esp-idf-1",
+            "idfInstalled": {
+                "esp-idf-1": { "version": "4.4", "python": "C:/python.exe", "path": "C:/esp", "custom": 1 },
+                "broken": "value"
+            }
+        }"#;
+        let (config, migrated) = EspIdfConfig::parse_and_migrate(content).unwrap();
+        assert!(migrated);
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert_eq!(config.git_path, "C:/git/git.exe");
+        assert_eq!(config.idf_tools_path, "");
+        assert_eq!(config.idf_installed.len(), 1);
+        assert_eq!(config.get_selected_installation().unwrap().version, "4.4");
+        assert_eq!(config.get_property("gitPatth"), None);
+        assert_eq!(config.get_property("$schema"), Some("http://json-schema.org/schema#".to_string()));
+    }
+
+    #[test]
+    fn test_round_trip_preserves_unknown_properties() {
+        let content = r#"{ "configVersion": 1, "_comment": "x", "gitPath": "", "idfToolsPath": "/t",
+            "idfSelectedId": "", "idfInstalled": { "a": { "version": "5.0", "python": "", "path": "/a", "eclipse": "y" } } }"#;
+        let (config, migrated) = EspIdfConfig::parse_and_migrate(content).unwrap();
+        assert!(!migrated);
+        let (reparsed, _) = EspIdfConfig::parse_and_migrate(&config.to_json().dump()).unwrap();
+        assert_eq!(config, reparsed);
+        assert_eq!(reparsed.extra["_comment"], "x");
+        assert_eq!(reparsed.idf_installed["a"].get_property("eclipse"), Some("y".to_string()));
+        assert!(!config.to_json()["idfInstalled"]["a"].has_key("name"));
+    }
+
+    #[test]
+    fn test_find_id_by_name() {
+        let mut config = EspIdfConfig::new("/t".to_string());
+        let mut installation = IdfInstallation::new("/esp/v5.1".to_string(), "5.1".to_string(), "".to_string());
+        installation.name = "v5.1".to_string();
+        config.idf_installed.insert("esp-idf-1".to_string(), installation);
+        config.idf_installed.insert("esp-idf-2".to_string(), IdfInstallation::new("/esp/master".to_string(), "5.2".to_string(), "".to_string()));
+        assert_eq!(config.find_id_by_name("v5.1"), Some("esp-idf-1".to_string()));
+        assert_eq!(config.find_id_by_name(""), None);
+        assert_eq!(config.find_id_by_name("master"), None);
+    }
+
+    #[test]
+    fn test_set_property() {
+        let mut config = EspIdfConfig::new("/t".to_string());
+        config.set_property("gitPath", "/usr/bin/git".to_string());
+        config.set_property("configVersion", "7".to_string());
+        config.set_property("custom", "x".to_string());
+        assert_eq!(config.git_path, "/usr/bin/git");
+        assert_eq!(config.config_version, CONFIG_VERSION);
+        assert_eq!(config.get_property("custom"), Some("x".to_string()));
+
+        config.set_property("gitPath", "".to_string());
+        config.set_property("custom", "".to_string());
+        assert_eq!(config.get_property("custom"), None);
+        let document = config.to_json();
+        assert_eq!(document["gitPath"], "");
+        assert!(!document.has_key("custom"));
+        assert_eq!(document[CLEARED_PROPERTIES_KEY], json::array!["custom", "gitPath"]);
+        let (reparsed, _) = EspIdfConfig::parse_and_migrate(&document.dump()).unwrap();
+        assert_eq!(reparsed, config);
+        config.git_path = "/usr/bin/git".to_string();
+        assert_eq!(config.to_json()["gitPath"], "/usr/bin/git");
+
+        let mut installation = IdfInstallation::default();
+        installation.set_property("python", "/usr/bin/python3".to_string());
+        assert_eq!(installation.python, "/usr/bin/python3");
+    }
+
+    #[test]
+    fn test_reject_newer_version() {
+        assert!(EspIdfConfig::parse_and_migrate(r#"{ "configVersion": 999 }"#).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use clap::Arg;
+use clap_nested::Command;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::layers::find_in_ancestors;
+use crate::config::{find_idf_id, find_idf_id_by_path, get_version_major_minor, load_config_or_exit, normalize_path, EspIdfConfig, Result};
+
+/// Name of project-local file which pins ESP-IDF installation for the directory and its subdirectories.
+pub const PIN_FILE_NAME: &str = ".idf-env";
+
+/// Content of pin file. The file contains one of `version = v5.1`, `name = idf`, `path = ../esp-idf`,
+/// or just a bare reference which is matched against ID, name, path and version of installations.
+#[derive(Debug, PartialEq)]
+enum PinReference {
+    Version(String),
+    Name(String),
+    Path(String),
+    Any(String),
+}
+
+impl fmt::Display for PinReference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PinReference::Version(version) => write!(f, "version = {}", version),
+            PinReference::Name(name) => write!(f, "name = {}", name),
+            PinReference::Path(path) => write!(f, "path = {}", path),
+            PinReference::Any(reference) => write!(f, "{}", reference),
+        }
+    }
+}
+
+/// Origin of ESP-IDF installation used by a command.
+pub enum IdfSource {
+    CommandLine(String),
+    PinFile(PathBuf, String),
+    Selected,
+}
+
+impl fmt::Display for IdfSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdfSource::CommandLine(argument) => write!(f, "command line argument {}", argument),
+            IdfSource::PinFile(pin_path, reference) => write!(f, "pin file {} ({})", pin_path.display(), reference),
+            IdfSource::Selected => write!(f, "idfSelectedId in configuration"),
+        }
+    }
+}
+
+pub struct ResolvedIdf {
+    pub source: IdfSource,
+    /// ID of registered installation, missing when the path is not registered.
+    pub idf_id: Option<String>,
+    pub path: String,
+}
+
+fn parse_pin_file(content: &str) -> std::result::Result<PinReference, String> {
+    let line = content.lines()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .ok_or("file does not reference any installation")?;
+
+    let (key, value) = match line.find('=') {
+        Some(position) => (line[..position].trim(), line[position + 1..].trim().trim_matches('"')),
+        None => return Ok(PinReference::Any(line.trim_matches('"').to_string())),
+    };
+    if value.is_empty() {
+        return Err(format!("missing value of '{}'", key));
+    }
+    match key {
+        "version" => Ok(PinReference::Version(value.to_string())),
+        "name" => Ok(PinReference::Name(value.to_string())),
+        "path" => Ok(PinReference::Path(value.to_string())),
+        _ => Err(format!("unknown key '{}', expected version, name or path", key)),
+    }
+}
+
+/// Find installation by version. Exact match wins over major.minor match,
+/// the selected installation is preferred when more installations match.
+fn find_idf_id_by_version(config: &EspIdfConfig, version: &str) -> Option<String> {
+    let wanted = version.trim_start_matches('v');
+    let matching: Vec<&String> = config.idf_installed.iter()
+        .filter(|(_, installation)| installation.version.trim_start_matches('v') == wanted)
+        .map(|(idf_id, _)| idf_id)
+        .collect();
+    let matching = if matching.is_empty() {
+        config.idf_installed.iter()
+            .filter(|(_, installation)| get_version_major_minor(&installation.version) == wanted)
+            .map(|(idf_id, _)| idf_id)
+            .collect()
+    } else {
+        matching
+    };
+    matching.iter()
+        .find(|idf_id| ***idf_id == config.idf_selected_id)
+        .or_else(|| matching.first())
+        .map(|idf_id| idf_id.to_string())
+}
+
+fn resolve_path_reference(config: &EspIdfConfig, pin_directory: &Path, path: &str) -> (Option<String>, String) {
+    let idf_path = normalize_path(&pin_directory.join(path).display().to_string());
+    (find_idf_id_by_path(config, &idf_path), idf_path)
+}
+
+fn resolve_pin_reference(config: &EspIdfConfig, pin_directory: &Path, reference: &PinReference) -> Option<(Option<String>, String)> {
+    let idf_id = match reference {
+        PinReference::Version(version) => find_idf_id_by_version(config, version),
+        PinReference::Name(name) => config.find_id_by_name(name),
+        PinReference::Path(path) => return Some(resolve_path_reference(config, pin_directory, path)),
+        PinReference::Any(reference) => {
+            match find_idf_id(config, reference).or_else(|| find_idf_id_by_version(config, reference)) {
+                Some(idf_id) => Some(idf_id),
+                None if pin_directory.join(reference).is_dir() => return Some(resolve_path_reference(config, pin_directory, reference)),
+                None => None,
+            }
+        }
+    }?;
+    let idf_path = config.idf_installed[&idf_id].path.clone();
+    Some((Some(idf_id), idf_path))
+}
+
+fn resolve_pin_file(config: &EspIdfConfig, pin_path: &Path) -> Result<ResolvedIdf> {
+    let content = fs::read_to_string(pin_path)
+        .map_err(|e| format!("Unable to read {}: {}", pin_path.display(), e))?;
+    let reference = parse_pin_file(&content)
+        .map_err(|e| format!("Invalid pin file {}: {}", pin_path.display(), e))?;
+    let pin_directory = pin_path.parent().unwrap_or_else(|| Path::new("."));
+    match resolve_pin_reference(config, pin_directory, &reference) {
+        Some((idf_id, path)) => Ok(ResolvedIdf {
+            source: IdfSource::PinFile(pin_path.to_path_buf(), reference.to_string()),
+            idf_id,
+            path,
+        }),
+        None => Err(format!("ESP-IDF installation '{}' pinned by {} is not registered. Use: idf-env config list",
+                            reference, pin_path.display()).into()),
+    }
+}
+
+/// Resolve installation for a command: --name or --idf-path argument first, then the nearest
+/// pin file starting in the given directory, then the globally selected installation.
+pub fn resolve_idf(config: &EspIdfConfig, matches: Option<&clap::ArgMatches<'_>>, directory: &Path) -> Result<ResolvedIdf> {
+    if let Some(matches) = matches {
+        if let Some(name) = matches.value_of("name") {
+            return match config.find_id_by_name(name) {
+                Some(idf_id) => Ok(ResolvedIdf {
+                    source: IdfSource::CommandLine(format!("--name {}", name)),
+                    path: config.idf_installed[&idf_id].path.clone(),
+                    idf_id: Some(idf_id),
+                }),
+                None => Err(format!("ESP-IDF installation with name '{}' not found. Use: idf-env config list", name).into()),
+            };
+        }
+        if let Some(idf_path) = matches.value_of("idf-path") {
+            return Ok(ResolvedIdf {
+                source: IdfSource::CommandLine(format!("--idf-path {}", idf_path)),
+                idf_id: find_idf_id_by_path(config, idf_path),
+                path: idf_path.to_string(),
+            });
+        }
+    }
+
+    if let Some(pin_path) = find_in_ancestors(directory, PIN_FILE_NAME) {
+        return resolve_pin_file(config, &pin_path);
+    }
+
+    Ok(ResolvedIdf {
+        source: IdfSource::Selected,
+        idf_id: config.get_selected_installation().map(|_| config.idf_selected_id.clone()),
+        path: config.get_selected_installation().map(|installation| installation.path.clone()).unwrap_or_default(),
+    })
+}
+
+fn get_which_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let config = load_config_or_exit();
+    let current_directory = std::env::current_dir()?;
+    let resolved = match resolve_idf(&config, Some(matches), &current_directory) {
+        Ok(resolved) => resolved,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    match (&resolved.idf_id, resolved.path.is_empty()) {
+        (_, true) => println!("No ESP-IDF installation is selected."),
+        (Some(idf_id), false) => {
+            let name = &config.idf_installed[idf_id].name;
+            if name.is_empty() {
+                println!("Installation: {}", idf_id);
+            } else {
+                println!("Installation: {} ({})", idf_id, name);
+            }
+        },
+        (None, false) => println!("Installation: not registered"),
+    }
+    if !resolved.path.is_empty() {
+        println!("Path: {}", resolved.path);
+    }
+    println!("Source: {}", resolved.source);
+    Ok(())
+}
+
+pub fn get_which_cmd<'a>() -> Command<'a, str> {
+    Command::new("which")
+        .description("Show which ESP-IDF installation is used in the current directory and why")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("idf-path")
+                    .short("i")
+                    .long("idf-path")
+                    .help("Path to ESP-IDF")
+                    .takes_value(true)
+            )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .help("Name of ESP-IDF installation")
+                        .takes_value(true)
+                )
+        })
+        .runner(|_args, matches|
+            get_which_runner(_args, matches)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::IdfInstallation;
+
+    #[test]
+    fn test_parse_pin_file() {
+        assert_eq!(parse_pin_file("# project pin\nversion = v5.1\n"), Ok(PinReference::Version("v5.1".to_string())));
+        assert_eq!(parse_pin_file("path = \"../esp-idf\""), Ok(PinReference::Path("../esp-idf".to_string())));
+        assert_eq!(parse_pin_file("idf\n"), Ok(PinReference::Any("idf".to_string())));
+        assert!(parse_pin_file("branch = master").is_err());
+        assert!(parse_pin_file("\n# empty\n").is_err());
+    }
+
+    #[test]
+    fn test_resolve_from_pin_file() {
+        let mut config = EspIdfConfig::new("/tools".to_string());
+        config.idf_installed.insert("esp-idf-1".to_string(), IdfInstallation::new("/esp/v4.4".to_string(), "v4.4.2".to_string(), "".to_string()));
+        config.idf_installed.insert("esp-idf-2".to_string(), IdfInstallation::new("/esp/v5.1".to_string(), "v5.1".to_string(), "".to_string()));
+        config.idf_selected_id = "esp-idf-2".to_string();
+
+        let project = std::env::temp_dir().join(format!("idf-env-pin-{}", std::process::id()));
+        let nested = project.join("main").join("src");
+        fs::create_dir_all(&nested).unwrap();
+
+        let resolved = resolve_idf(&config, None, &nested).unwrap();
+        assert!(matches!(resolved.source, IdfSource::Selected));
+        assert_eq!(resolved.path, "/esp/v5.1");
+
+        fs::write(project.join(PIN_FILE_NAME), "version = 4.4\n").unwrap();
+        let resolved = resolve_idf(&config, None, &nested).unwrap();
+        assert!(matches!(resolved.source, IdfSource::PinFile(_, _)));
+        assert_eq!(resolved.idf_id, Some("esp-idf-1".to_string()));
+
+        fs::write(project.join(PIN_FILE_NAME), "version = 3.3\n").unwrap();
+        assert!(resolve_idf(&config, None, &nested).is_err());
+        fs::remove_dir_all(project).unwrap();
+    }
+}
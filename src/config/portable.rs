@@ -0,0 +1,260 @@
+use clap::Arg;
+use clap_nested::Command;
+use json::JsonValue;
+use std::fs;
+
+use crate::config::{get_idf_id, get_tools_path, load_config_or_exit, normalize_path, update_config, EspIdfConfig, IdfInstallation, Result};
+use crate::idf::get_idf_base_directory;
+
+const EXPORT_FORMAT: &str = "idf-env-export";
+const EXPORT_VERSION: u32 = 1;
+const TOOLS_PATH_VARIABLE: &str = "${IDF_TOOLS_PATH}";
+const BASE_DIRECTORY_VARIABLE: &str = "${IDF_BASE}";
+
+/// Local directories which are replaced by variables in exported document, the most specific first.
+fn get_path_roots() -> Vec<(&'static str, String)> {
+    let mut roots = vec![
+        (TOOLS_PATH_VARIABLE, normalize_path(&get_tools_path())),
+        (BASE_DIRECTORY_VARIABLE, normalize_path(&get_idf_base_directory())),
+    ];
+    roots.sort_by(|a, b| b.1.len().cmp(&a.1.len()));
+    roots
+}
+
+/// Rewrite path located under one of the roots to form ${VARIABLE}/relative/path.
+/// Paths outside of the roots are exported unchanged.
+fn to_portable_path(path: &str, roots: &[(&str, String)]) -> String {
+    let normalized = normalize_path(path);
+    for (variable, root) in roots {
+        if root.is_empty() {
+            continue;
+        }
+        if normalized == *root {
+            return variable.to_string();
+        }
+        if let Some(relative) = normalized.strip_prefix(&format!("{}/", root)) {
+            return format!("{}/{}", variable, relative);
+        }
+    }
+    path.to_string()
+}
+
+fn from_portable_path(path: &str, roots: &[(&str, String)]) -> String {
+    for (variable, root) in roots {
+        if let Some(relative) = path.strip_prefix(variable) {
+            return format!("{}{}", root, relative);
+        }
+    }
+    path.to_string()
+}
+
+fn export_config(config: &EspIdfConfig, roots: &[(&str, String)]) -> JsonValue {
+    let mut installations = JsonValue::new_array();
+    for (idf_id, installation) in &config.idf_installed {
+        let mut entry = json::object! {
+            "name": installation.name.clone(),
+            "version": installation.version.clone(),
+            "python": to_portable_path(&installation.python, roots),
+            "path": to_portable_path(&installation.path, roots)
+        };
+        if *idf_id == config.idf_selected_id {
+            entry["selected"] = true.into();
+        }
+        let _ = installations.push(entry);
+    }
+    json::object! {
+        "format": EXPORT_FORMAT,
+        "formatVersion": EXPORT_VERSION,
+        "installations": installations
+    }
+}
+
+/// Outcome of merging exported document into local configuration.
+#[derive(Debug, Default)]
+struct ImportReport {
+    imported: Vec<String>,
+    conflicts: Vec<String>,
+}
+
+/// Merge exported installations into configuration. Entries which clash with local ones by name,
+/// or which are registered with different properties, are reported and kept intact unless overwrite is set.
+fn import_config(config: &mut EspIdfConfig, document: &JsonValue, roots: &[(&str, String)], overwrite: bool) -> Result<ImportReport> {
+    if document["format"].as_str() != Some(EXPORT_FORMAT) {
+        return Err("Document is not an idf-env export".into());
+    }
+    let format_version = document["formatVersion"].as_u32().unwrap_or(0);
+    if format_version > EXPORT_VERSION {
+        return Err(format!("Export format version {} is not supported, upgrade idf-env", format_version).into());
+    }
+
+    let mut report = ImportReport::default();
+    for entry in document["installations"].members() {
+        let idf_path = from_portable_path(entry["path"].as_str().unwrap_or(""), roots);
+        if idf_path.is_empty() {
+            report.conflicts.push("Installation without path skipped".to_string());
+            continue;
+        }
+        let idf_id = get_idf_id(&idf_path);
+        let mut installation = IdfInstallation::new(
+            idf_path.clone(),
+            entry["version"].as_str().unwrap_or("").to_string(),
+            from_portable_path(entry["python"].as_str().unwrap_or(""), roots),
+        );
+        installation.name = entry["name"].as_str().unwrap_or("").to_string();
+
+        if let Some(other_id) = config.find_id_by_name(&installation.name) {
+            if other_id != idf_id {
+                report.conflicts.push(format!("{}: name '{}' is already used by installation {}", idf_path, installation.name, other_id));
+                continue;
+            }
+        }
+        if let Some(existing) = config.idf_installed.get_mut(&idf_id) {
+            if existing.version != installation.version || existing.python != installation.python || existing.name != installation.name {
+                if !overwrite {
+                    report.conflicts.push(format!("{}: already registered as {} with different properties", idf_path, idf_id));
+                    continue;
+                }
+                existing.name = installation.name;
+                existing.version = installation.version;
+                existing.python = installation.python;
+            }
+        } else {
+            config.idf_installed.insert(idf_id.clone(), installation);
+        }
+        if entry["selected"].as_bool().unwrap_or(false) && (config.idf_selected_id.is_empty() || overwrite) {
+            config.idf_selected_id = idf_id.clone();
+        }
+        report.imported.push(format!("{} ({})", idf_id, idf_path));
+    }
+    Ok(report)
+}
+
+fn get_export_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let document = export_config(&load_config_or_exit(), &get_path_roots());
+    match matches.value_of("output") {
+        Some(output_path) => {
+            if let Err(e) = fs::write(output_path, format!("{:#}", document)) {
+                println!("Unable to write {}: {}", output_path, e);
+                std::process::exit(1);
+            }
+            println!("Exported {} installation(s) to {}", document["installations"].len(), output_path);
+        },
+        None => println!("{:#}", document)
+    }
+    Ok(())
+}
+
+fn get_import_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let input_path = matches.value_of("file").unwrap();
+    let document = match fs::read_to_string(input_path).map_err(|e| e.to_string()).and_then(|content| json::parse(&content).map_err(|e| e.to_string())) {
+        Ok(document) => document,
+        Err(e) => {
+            println!("Unable to read {}: {}", input_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    let roots = get_path_roots();
+    let overwrite = matches.is_present("overwrite");
+    let report = match update_config(|config| import_config(config, &document, &roots, overwrite)) {
+        Ok(Ok(report)) => report,
+        Ok(Err(e)) | Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+
+    for imported in &report.imported {
+        println!("Imported: {}", imported);
+    }
+    for conflict in &report.conflicts {
+        println!("Conflict: {}", conflict);
+    }
+    println!("Imported {} installation(s), {} conflict(s).", report.imported.len(), report.conflicts.len());
+    if !report.conflicts.is_empty() {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+pub fn get_export_cmd<'a>() -> Command<'a, str> {
+    Command::new("export")
+        .description("Export registered installations with paths relative to IDF_TOOLS_PATH and ESP-IDF base directory")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Write document to file instead of standard output")
+                    .takes_value(true)
+            )
+        })
+        .runner(|_args, matches|
+            get_export_runner(_args, matches)
+        )
+}
+
+pub fn get_import_cmd<'a>() -> Command<'a, str> {
+    Command::new("import")
+        .description("Merge installations exported by config export into local configuration")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("file")
+                    .help("Path to exported document")
+                    .required(true)
+                    .index(1)
+            )
+                .arg(
+                    Arg::with_name("overwrite")
+                        .short("w")
+                        .long("overwrite")
+                        .help("Replace properties and selection of installations which are already registered")
+                )
+        })
+        .runner(|_args, matches|
+            get_import_runner(_args, matches)
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_roots(tools_path: &str, base_directory: &str) -> Vec<(&'static str, String)> {
+        vec![(TOOLS_PATH_VARIABLE, tools_path.to_string()), (BASE_DIRECTORY_VARIABLE, base_directory.to_string())]
+    }
+
+    #[test]
+    fn test_portable_path() {
+        let roots = get_roots("C:/Espressif", "C:/esp");
+        assert_eq!(to_portable_path("C:\\esp\\v4.4", &roots), "${IDF_BASE}/v4.4");
+        assert_eq!(to_portable_path("C:/Espressif/python_env/idf4.4_py3.8_env/Scripts/python.exe", &roots),
+                   "${IDF_TOOLS_PATH}/python_env/idf4.4_py3.8_env/Scripts/python.exe");
+        assert_eq!(to_portable_path("C:/esp-other/v4.4", &roots), "C:/esp-other/v4.4");
+        assert_eq!(from_portable_path("${IDF_BASE}/v4.4", &get_roots("/home/user/.espressif", "/home/user/esp")), "/home/user/esp/v4.4");
+    }
+
+    #[test]
+    fn test_export_import() {
+        let source_roots = get_roots("C:/Espressif", "C:/esp");
+        let mut source = EspIdfConfig::new("C:/Espressif".to_string());
+        let mut installation = IdfInstallation::new("C:/esp/v4.4".to_string(), "v4.4".to_string(), "C:/Espressif/python/python.exe".to_string());
+        installation.name = "idf".to_string();
+        source.idf_installed.insert(get_idf_id("C:/esp/v4.4"), installation);
+        source.idf_selected_id = get_idf_id("C:/esp/v4.4");
+        let document = export_config(&source, &source_roots);
+
+        let target_roots = get_roots("/opt/espressif", "/opt/esp");
+        let mut target = EspIdfConfig::new("/opt/espressif".to_string());
+        let report = import_config(&mut target, &document, &target_roots, false).unwrap();
+        let idf_id = get_idf_id("/opt/esp/v4.4");
+        assert_eq!(report.imported.len(), 1);
+        assert_eq!(target.idf_selected_id, idf_id);
+        assert_eq!(target.idf_installed[&idf_id].python, "/opt/espressif/python/python.exe");
+
+        target.idf_installed.get_mut(&idf_id).unwrap().version = "v4.3".to_string();
+        let report = import_config(&mut target, &document, &target_roots, false).unwrap();
+        assert_eq!(report.conflicts.len(), 1);
+        assert_eq!(target.idf_installed[&idf_id].version, "v4.3");
+    }
+}
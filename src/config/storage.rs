@@ -0,0 +1,125 @@
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Advisory lock shared by all processes which modify esp_idf.json, e.g. idf-env, installer or Eclipse plugin.
+/// The lock is released when the guard is dropped.
+pub struct ConfigLock {
+    file: File,
+}
+
+impl ConfigLock {
+    pub fn acquire(json_path: &str) -> Result<ConfigLock> {
+        let lock_path = format!("{}.lock", json_path);
+        if let Some(parent) = Path::new(&lock_path).parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&lock_path)
+            .map_err(|e| format!("Unable to open lock file {}: {}", lock_path, e))?;
+
+        if file.try_lock_exclusive().is_err() {
+            println!("Waiting for other process to release {}", lock_path);
+            file.lock_exclusive()
+                .map_err(|e| format!("Unable to lock {}: {}", lock_path, e))?;
+        }
+        Ok(ConfigLock { file })
+    }
+}
+
+impl Drop for ConfigLock {
+    fn drop(&mut self) {
+        let _ = FileExt::unlock(&self.file);
+    }
+}
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Temporary file next to the path, unique for each call also among threads of one process.
+pub fn get_temp_path(path: &str) -> String {
+    format!("{}.{}-{}.tmp", path, std::process::id(), TEMP_COUNTER.fetch_add(1, Ordering::Relaxed))
+}
+
+/// Write content to temporary file in the same directory and rename it over the target,
+/// so that readers never observe partially written file.
+pub fn write_atomic(path: &str, content: &str) -> Result<()> {
+    let temp_path = get_temp_path(path);
+    {
+        let mut temp_file = File::create(&temp_path)
+            .map_err(|e| format!("Unable to create {}: {}", temp_path, e))?;
+        temp_file.write_all(content.as_bytes())?;
+        temp_file.sync_all()?;
+    }
+
+    if let Err(e) = fs::rename(&temp_path, path) {
+        let _ = fs::remove_file(&temp_path);
+        return Err(format!("Unable to replace {}: {}", path, e).into());
+    }
+    Ok(())
+}
+
+/// Backup of the same content made by an earlier run, so that repeated reads of a corrupted file
+/// do not fill the directory with copies.
+fn find_backup(path: &str, content: &[u8]) -> Option<String> {
+    let path = Path::new(path);
+    let prefix = format!("{}.corrupted-", path.file_name()?.to_string_lossy());
+    fs::read_dir(path.parent()?).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+        .find(|entry| fs::read(entry.path()).map(|backup| backup == content).unwrap_or(false))
+        .map(|entry| entry.path().display().to_string())
+}
+
+/// Keep copy of a file which could not be parsed and return path to the copy.
+pub fn backup_corrupted(path: &str) -> Result<String> {
+    let content = fs::read(path)?;
+    if let Some(backup_path) = find_backup(path, &content) {
+        return Ok(backup_path);
+    }
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let backup_path = format!("{}.corrupted-{}", path, timestamp);
+    fs::write(&backup_path, content)?;
+    Ok(backup_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_atomic_replaces_content() {
+        let directory = std::env::temp_dir().join(format!("idf-env-storage-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("esp_idf.json").display().to_string();
+
+        write_atomic(&path, "first").unwrap();
+        {
+            let _lock = ConfigLock::acquire(&path).unwrap();
+            write_atomic(&path, "second").unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "second");
+        assert_ne!(get_temp_path(&path), get_temp_path(&path));
+        let names: Vec<String> = fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert!(names.iter().all(|name| !name.ends_with(".tmp")));
+
+        let backup_path = backup_corrupted(&path).unwrap();
+        assert_eq!(fs::read_to_string(&backup_path).unwrap(), "second");
+        // Unchanged file is not copied again
+        assert_eq!(backup_corrupted(&path).unwrap(), backup_path);
+        let backups = fs::read_dir(&directory).unwrap()
+            .filter(|entry| entry.as_ref().unwrap().file_name().to_string_lossy().contains(".corrupted-"))
+            .count();
+        assert_eq!(backups, 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
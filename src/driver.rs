@@ -3,7 +3,12 @@ use clap_nested::{Command, Commander, MultiCommand};
 #[cfg(windows)]
 use std::collections::HashMap;
 
-use crate::package::prepare_package;
+#[cfg(windows)]
+use crate::package::prepare_signed_package;
+#[cfg(windows)]
+use crate::package::offline::{check_artifacts, Artifact};
+#[cfg(windows)]
+use crate::package::signature::{get_signature_artifact, get_verification_keys};
 use crate::config;
 
 #[cfg(windows)]
@@ -162,36 +167,39 @@ fn download_drivers(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::result
 
 #[cfg(windows)]
 fn download_drivers(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
-    if _matches.is_present("silabs") {
-        match prepare_package("https://www.silabs.com/documents/public/software/CP210x_Universal_Windows_Driver.zip".to_string(),
-                        "cp210x.zip",
-                        get_driver_path("silabs-2021-05-03".to_string())) {
-                            Ok(_) => { println!("Ok"); },
-                            Err(_e) => { println!("Failed");}
-                        }
-    }
-    if _matches.is_present("ftdi") {
-        match prepare_package("https://www.ftdichip.com/Drivers/CDM/CDM%20v2.12.28%20WHQL%20Certified.zip".to_string(),
-                        "ftdi.zip",
-                        get_driver_path("ftdi-2021-05-03".to_string())) {
-                            Ok(_) => { println!("Ok"); },
-                            Err(_e) => { println!("Failed");}
-                        }
-    }
-    if _matches.is_present("espressif") {
-        match prepare_package("https://dl.espressif.com/dl/idf-driver/idf-driver-esp32-usb-jtag-2021-07-15.zip".to_string(),
-                        "idf-driver-esp32-usb-jtag-2021-07-15.zip",
-                        get_driver_path("idf-driver-esp32-usb-jtag-2021-07-15".to_string())) {
-                            Ok(_) => { println!("Ok"); },
-                            Err(_e) => { println!("Failed");}
-                        }
+    // Flag, URL, archive in dist directory and destination directory of each driver
+    let drivers = [
+        ("silabs", "https://www.silabs.com/documents/public/software/CP210x_Universal_Windows_Driver.zip",
+         "cp210x.zip", "silabs-2021-05-03"),
+        ("ftdi", "https://www.ftdichip.com/Drivers/CDM/CDM%20v2.12.28%20WHQL%20Certified.zip",
+         "ftdi.zip", "ftdi-2021-05-03"),
+        ("espressif", "https://dl.espressif.com/dl/idf-driver/idf-driver-esp32-usb-jtag-2021-07-15.zip",
+         "idf-driver-esp32-usb-jtag-2021-07-15.zip", "idf-driver-esp32-usb-jtag-2021-07-15"),
+        ("wch", "https://www.wch.cn/downloads/file/314.html",
+         "whc-ch343ser.zip", "whc-ch343ser-2022-08-02"),
+    ];
+    let selected: Vec<_> = drivers.iter().filter(|(flag, ..)| _matches.is_present(flag)).collect();
+
+    // Offline mode reports all missing archives before the first driver is extracted
+    let mut artifacts: Vec<Artifact> = selected.iter()
+        .map(|(_, url, archive, _)| Artifact { url: url.to_string(), archive: config::get_dist_path(archive) })
+        .collect();
+    let signatures: Vec<Artifact> = artifacts.iter()
+        .filter(|artifact| get_verification_keys(&artifact.url).is_ok_and(|keys| !keys.is_empty()))
+        .map(|artifact| get_signature_artifact(&artifact.url, &artifact.archive))
+        .collect();
+    artifacts.extend(signatures);
+    if let Err(e) = check_artifacts(&artifacts) {
+        e.exit("Failed: ");
     }
-    if _matches.is_present("wch") {
-        match prepare_package("https://www.wch.cn/downloads/file/314.html".to_string(),
-                        "whc-ch343ser.zip",
-                        get_driver_path("whc-ch343ser-2022-08-02".to_string())) {
+
+    for (_, url, archive, directory) in selected {
+        match prepare_signed_package(url.to_string(),
+                        archive,
+                        get_driver_path(directory.to_string()),
+                        None) {
                             Ok(_) => { println!("Ok"); },
-                            Err(_e) => { println!("Failed");}
+                            Err(e) => { e.exit("Failed: "); }
                         }
     }
 
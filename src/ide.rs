@@ -5,7 +5,7 @@ use std::fs::File;
 use std::io::{self, BufRead, Write};
 
 use std::path::Path;
-use crate::package::{prepare_package_strip_prefix};
+use crate::package::{prepare_signed_package_strip_prefix};
 
 const DEFAULT_IDE_URL:&str = "https://dl.espressif.com/dl/idf-eclipse-plugin/ide/Espressif-IDE-2.4.2-win32.win32.x86_64.zip";
 const DEFAULT_IDE_FILE:&str = "Espressif-IDE-2.4.2-win32.win32.x86_64.zip";
@@ -19,12 +19,13 @@ struct Ide {
 
 fn install_ide(ide:&Ide) {
 
-    match prepare_package_strip_prefix(&ide.dist_url,
+    match prepare_signed_package_strip_prefix(&ide.dist_url,
                                  &ide.dist_file,
                                  ide.destination_dir.clone(),
-                                 &ide.prefix) {
+                                 &ide.prefix,
+                                 None) {
                                     Ok(_) => { println!("Ok"); },
-                                    Err(_e) => { println!("Failed");}
+                                    Err(e) => { e.exit("Failed: "); }
                                 }
 
 
@@ -1,3 +1,5 @@
+pub mod tools;
+
 use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 use git2::{Repository};
@@ -7,18 +9,28 @@ use tokio::{runtime::Handle};
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 use std::env;
+#[cfg(unix)]
+use dirs::home_dir;
 
 use std::process::Stdio;
 use std::io::Read;
 
 use std::time::{Instant};
 
-use crate::config::{add_idf_config, get_git_path, get_tool_path, get_dist_path, get_python_env_path, update_property};
+use crate::config::{add_idf_config, get_git_path, get_tool_path, get_dist_path, get_python_env_path, update_config};
 use crate::config::get_tools_path;
-use crate::config::get_selected_idf_path;
+use crate::config::{get_idf_path_from_matches, resolve_active_idf};
 use crate::package::prepare_package;
+use crate::package::scheduler::get_parallelism;
+#[cfg(windows)]
+use crate::package::offline::{check_artifacts, Artifact};
 use crate::shell::run_command;
 
+#[cfg(windows)]
+const IDF_GIT_URL: &str = "https://dl.espressif.com/dl/idf-git/idf-git-2.30.1-win64.zip";
+#[cfg(windows)]
+const IDF_PYTHON_URL: &str = "https://dl.espressif.com/dl/idf-python/idf-python-3.8.7-embed-win64.zip";
+
 async fn excecute_async(command: String, arguments:Vec<String>){
     let _child_process = tokio::process::Command::new(command)
         .args(arguments)
@@ -109,12 +121,12 @@ fn get_reset_cmd<'a>() -> Command<'a, str> {
 }
 
 #[cfg(windows)]
-fn get_idf_base_directory() -> String {
+pub fn get_idf_base_directory() -> String {
     "C:/esp".to_string()
 }
 
 #[cfg(unix)]
-fn get_idf_base_directory() -> String {
+pub fn get_idf_base_directory() -> String {
     home_dir().unwrap().display().to_string() + "/esp"
 }
 
@@ -128,25 +140,56 @@ fn get_esp_idf_directory(idf_name:String) -> String {
     format!("{}/{}", get_idf_base_directory(), idf_name)
 }
 
-fn get_install_runner(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+/// Install tools required by ESP-IDF natively from its tools/tools.json instead of idf_tools.py.
+fn install_idf_tools(esp_idf: &str, parallelism: usize) {
+    let platform = match guess_host_triple::guess_host_triple().and_then(tools::get_platform) {
+        Some(platform) => platform,
+        None => {
+            println!("Unable to install ESP-IDF tools: unsupported host platform");
+            std::process::exit(1);
+        }
+    };
+    let manifest = match tools::ToolsManifest::from_idf_path(esp_idf) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Err(e) = tools::install_tools(&manifest, platform, parallelism) {
+        e.exit("Unable to install ESP-IDF tools: ");
+    }
+}
+
+fn get_install_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
     let esp_idf = get_esp_idf_directory("esp-idf-master/".to_string());
     println!("ESP-IDF Path: {}", esp_idf);
 
+    // Offline mode reports all missing archives before the first one is extracted
+    #[cfg(windows)]
+    if let Err(e) = check_artifacts(&[
+        Artifact { url: IDF_GIT_URL.to_string(), archive: get_dist_path("idf-git-2.30.1-win64.zip") },
+        Artifact { url: IDF_PYTHON_URL.to_string(), archive: get_dist_path("idf-python-3.8.7-embed-win64.zip") },
+    ]) {
+        e.exit("Failed: ");
+    }
     #[cfg(windows)]
-    match prepare_package("https://dl.espressif.com/dl/idf-git/idf-git-2.30.1-win64.zip".to_string(),
-        get_dist_path("idf-git-2.30.1-win64.zip").as_str(),
-        get_tool_path("idf-git/2.30.1".to_string())
+    match prepare_package(IDF_GIT_URL.to_string(),
+        "idf-git-2.30.1-win64.zip",
+        get_tool_path("idf-git/2.30.1".to_string()),
+        None
     ) {
         Ok(_) => { println!("Ok"); },
-        Err(_e) => { println!("Failed");}
+        Err(e) => { e.exit("Failed: "); }
     }
     #[cfg(windows)]
-    match prepare_package("https://dl.espressif.com/dl/idf-python/idf-python-3.8.7-embed-win64.zip".to_string(),
-        get_dist_path("idf-python-3.8.7-embed-win64.zip").as_str(),
-        get_tool_path("idf-python/3.8.7".to_string())
+    match prepare_package(IDF_PYTHON_URL.to_string(),
+        "idf-python-3.8.7-embed-win64.zip",
+        get_tool_path("idf-python/3.8.7".to_string()),
+        None
     ) {
         Ok(_) => { println!("Ok"); },
-        Err(_e) => { println!("Failed");}
+        Err(e) => { e.exit("Failed: "); }
     }
 
     #[cfg(windows)]
@@ -154,7 +197,9 @@ fn get_install_runner(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::resu
     #[cfg(unix)]
     let git_path = "/usr/bin/git".to_string();
 
-    update_property("gitPath".to_string(), git_path.clone());
+    if let Err(e) = update_config(|config| config.git_path = git_path.clone()) {
+        println!("{}", e);
+    }
 
     #[cfg(windows)]
     let python_path = get_tool_path("idf-python/3.8.7/python.exe".to_string());
@@ -197,16 +242,9 @@ fn get_install_runner(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::resu
     #[cfg(unix)]
     let python_path = format!("{}/bin/python", virtual_env_path);
 
-    let idf_tools = format!("{}/tools/idf_tools.py", esp_idf);
-
-    let mut arguments: Vec<String> = [].to_vec();
-    arguments.push(idf_tools.clone());
-    arguments.push("install".to_string());
-    match run_command(python_path.clone(), arguments, "".to_string()) {
-        Ok(_) => { println!("Ok"); },
-        Err(_e) => { println!("Failed");}
-    }
+    install_idf_tools(&esp_idf, get_parallelism(matches.value_of("jobs")));
 
+    let idf_tools = format!("{}/tools/idf_tools.py", esp_idf);
     let mut arguments: Vec<String> = [].to_vec();
     arguments.push(idf_tools);
     arguments.push("install-python-env".to_string());
@@ -215,7 +253,9 @@ fn get_install_runner(_args: &str, _matches: &clap::ArgMatches<'_>) -> std::resu
         Err(_e) => { println!("Failed");}
     }
 
-    add_idf_config(esp_idf, "4.4".to_string(), python_path);
+    if let Err(e) = add_idf_config(esp_idf, "4.4".to_string(), python_path, "".to_string(), true) {
+        println!("{}", e);
+    }
     Ok(())
 }
 
@@ -259,6 +299,12 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                         .long("verbose")
                         .takes_value(false)
                         .help("display diagnostic log after installation"))
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .takes_value(true)
+                        .help("Number of concurrent downloads, default IDF_ENV_DOWNLOAD_PARALLELISM or 4"))
         })
         .runner(|_args, matches|
             get_install_runner(_args, matches)
@@ -272,7 +318,7 @@ fn get_shell() -> String {
 
 #[cfg(unix)]
 fn get_initializer() -> String {
-    format!("{}/export.sh", get_selected_idf_path())
+    format!("{}/export.sh", resolve_active_idf(None).path)
 }
 
 #[cfg(unix)]
@@ -301,6 +347,10 @@ fn get_initializer_arguments() -> Vec<String> {
     arguments.push("-NoExit".to_string());
     arguments.push("-File".to_string());
     arguments.push(get_initializer());
+    if let Some(idf_id) = resolve_active_idf(None).idf_id {
+        arguments.push("-IdfId".to_string());
+        arguments.push(idf_id);
+    }
     arguments
 }
 
@@ -373,8 +423,7 @@ fn run_build(idf_path: &String, _shell_initializer: &String) -> std::result::Res
 
 fn get_build_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
     let build_repetitions:i32 = matches.value_of("repeat").unwrap().to_string().parse().unwrap();
-    let idf_path = matches.value_of("idf-path")
-        .unwrap_or(&*get_selected_idf_path()).to_string();
+    let idf_path = get_idf_path_from_matches(matches);
 
     let initializer = get_initializer();
     println!("Number of CPU cores: {}", num_cpus::get());
@@ -431,8 +480,7 @@ fn change_submodules_mirror(mut repo: Repository, submodule_url: String) {
 }
 
 fn get_mirror_switch_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
-    let idf_path = matches.value_of("idf-path")
-        .unwrap_or(&*get_selected_idf_path()).to_string();
+    let idf_path = get_idf_path_from_matches(matches);
     let url = matches.value_of("url")
         .unwrap().to_string();
     let submodule_url = matches.value_of("submodule-url")
@@ -529,6 +577,13 @@ pub fn get_build_cmd<'a>() -> Command<'a, str> {
                         .help("Path to ESP IDF source code repository")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .help("Custom name of registered ESP-IDF installation")
+                        .takes_value(true)
+                )
                 .arg(
                     Arg::with_name("tools-path")
                         .short("t")
@@ -560,6 +615,13 @@ pub fn get_mirror_cmd<'a>() -> Command<'a, str> {
                         .help("Path to ESP IDF source code repository")
                         .takes_value(true)
                 )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .help("Custom name of registered ESP-IDF installation")
+                        .takes_value(true)
+                )
                 .arg(
                     Arg::with_name("submodule-url")
                         .short("s")
@@ -0,0 +1,348 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use json::JsonValue;
+
+use crate::config::{get_dist_path, get_tool_path};
+use crate::package::extract::extract_archive_strip_components;
+use crate::package::progress::format_bytes;
+use crate::package::scheduler::Scheduler;
+use crate::package::staging::{extract_staged, is_installed};
+use crate::package::PackageError;
+use crate::receipt::{self, Receipt};
+
+/// Platform independent archives are stored under this key.
+const PLATFORM_ANY: &str = "any";
+
+/// Download of one tool version for one platform.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Download {
+    pub url: String,
+    pub size: u64,
+    pub sha256: String,
+}
+
+impl Download {
+    pub fn get_file_name(&self) -> &str {
+        self.url.rsplit('/').next().unwrap_or(&self.url)
+    }
+
+    /// Expected SHA-256 of the archive, None when tools.json does not provide it.
+    pub fn get_sha256(&self) -> Option<&str> {
+        Some(self.sha256.as_str()).filter(|sha256| !sha256.is_empty())
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToolVersion {
+    pub name: String,
+    /// recommended, supported or deprecated.
+    pub status: String,
+    /// Platform key, e.g. linux-amd64 or win64, mapped to download.
+    pub downloads: BTreeMap<String, Download>,
+}
+
+impl ToolVersion {
+    /// Download for the platform. Platform independent archive is used when there is no specific one,
+    /// Apple Silicon falls back to Intel build which runs under Rosetta.
+    pub fn get_download(&self, platform: &str) -> Option<&Download> {
+        self.downloads.get(platform)
+            .or_else(|| if platform == "macos-arm64" { self.downloads.get("macos") } else { None })
+            .or_else(|| self.downloads.get(PLATFORM_ANY))
+    }
+}
+
+/// Values of tool which differ on some platforms, e.g. tool installed only on request on Windows.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PlatformOverride {
+    pub platforms: Vec<String>,
+    pub install: Option<String>,
+    pub export_paths: Option<Vec<Vec<String>>>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Tool {
+    pub name: String,
+    pub description: String,
+    /// always, on_request or never.
+    pub install: String,
+    /// Directories added to PATH, relative to the directory of the installed version.
+    pub export_paths: Vec<Vec<String>>,
+    pub export_vars: BTreeMap<String, String>,
+    /// Number of leading directories removed from archive entries.
+    pub strip_container_dirs: usize,
+    pub supported_targets: Vec<String>,
+    pub platform_overrides: Vec<PlatformOverride>,
+    pub versions: Vec<ToolVersion>,
+}
+
+impl Tool {
+    fn get_override(&self, platform: &str) -> Option<&PlatformOverride> {
+        self.platform_overrides.iter().find(|platform_override| platform_override.platforms.iter().any(|name| name == platform))
+    }
+
+    pub fn get_install(&self, platform: &str) -> &str {
+        self.get_override(platform).and_then(|platform_override| platform_override.install.as_deref()).unwrap_or(&self.install)
+    }
+
+    pub fn get_export_paths(&self, platform: &str) -> &[Vec<String>] {
+        self.get_override(platform).and_then(|platform_override| platform_override.export_paths.as_deref()).unwrap_or(&self.export_paths)
+    }
+
+    pub fn get_recommended_version(&self) -> Option<&ToolVersion> {
+        self.versions.iter().find(|version| version.status == "recommended")
+    }
+
+    /// Directories added to PATH for the tool installed in tool_directory.
+    pub fn get_export_directories(&self, platform: &str, tool_directory: &str) -> Vec<String> {
+        self.get_export_paths(platform).iter()
+            .map(|export_path| export_path.iter().filter(|part| !part.is_empty()).fold(PathBuf::from(tool_directory), |path, part| path.join(part)))
+            .map(|path| path.display().to_string())
+            .collect()
+    }
+
+    /// Variables exported for the tool, `${TOOL_PATH}` is replaced by tool_directory like idf_tools.py does.
+    pub fn get_export_vars(&self, tool_directory: &str) -> Vec<(String, String)> {
+        self.export_vars.iter().map(|(name, value)| (name.clone(), value.replace("${TOOL_PATH}", tool_directory))).collect()
+    }
+}
+
+/// Content of tools/tools.json of ESP-IDF.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ToolsManifest {
+    pub version: u32,
+    pub tools: Vec<Tool>,
+}
+
+fn get_string(value: &JsonValue) -> String {
+    value.as_str().unwrap_or("").to_string()
+}
+
+fn get_strings(value: &JsonValue) -> Vec<String> {
+    value.members().filter_map(|member| member.as_str()).map(|member| member.to_string()).collect()
+}
+
+fn get_export_paths(value: &JsonValue) -> Vec<Vec<String>> {
+    value.members().map(get_strings).collect()
+}
+
+fn parse_version(value: &JsonValue) -> ToolVersion {
+    let downloads = value.entries()
+        .filter(|(_, download)| download["url"].is_string())
+        .map(|(platform, download)| (platform.to_string(), Download {
+            url: get_string(&download["url"]),
+            size: download["size"].as_u64().unwrap_or(0),
+            sha256: get_string(&download["sha256"]),
+        }))
+        .collect();
+    ToolVersion { name: get_string(&value["name"]), status: get_string(&value["status"]), downloads }
+}
+
+fn parse_tool(value: &JsonValue) -> Tool {
+    Tool {
+        name: get_string(&value["name"]),
+        description: get_string(&value["description"]),
+        install: value["install"].as_str().unwrap_or("always").to_string(),
+        export_paths: get_export_paths(&value["export_paths"]),
+        export_vars: value["export_vars"].entries().map(|(name, value)| (name.to_string(), get_string(value))).collect(),
+        strip_container_dirs: value["strip_container_dirs"].as_usize().unwrap_or(0),
+        supported_targets: get_strings(&value["supported_targets"]),
+        platform_overrides: value["platform_overrides"].members()
+            .map(|platform_override| PlatformOverride {
+                platforms: get_strings(&platform_override["platforms"]),
+                install: platform_override["install"].as_str().map(|install| install.to_string()),
+                export_paths: Some(&platform_override["export_paths"]).filter(|paths| paths.is_array()).map(get_export_paths),
+            })
+            .collect(),
+        versions: value["versions"].members().map(parse_version).collect(),
+    }
+}
+
+impl ToolsManifest {
+    pub fn parse(content: &str) -> Result<ToolsManifest, String> {
+        let document = json::parse(content).map_err(|e| e.to_string())?;
+        if !document["tools"].is_array() {
+            return Err("Missing list of tools".to_string());
+        }
+        let tools = document["tools"].members().map(parse_tool).filter(|tool| !tool.name.is_empty()).collect();
+        Ok(ToolsManifest { version: document["version"].as_u32().unwrap_or(0), tools })
+    }
+
+    /// Manifest of ESP-IDF installed in idf_path.
+    pub fn from_idf_path(idf_path: &str) -> Result<ToolsManifest, String> {
+        let manifest_path = Path::new(idf_path).join("tools").join("tools.json");
+        let content = fs::read_to_string(&manifest_path).map_err(|e| format!("Unable to read {}: {}", manifest_path.display(), e))?;
+        ToolsManifest::parse(&content).map_err(|e| format!("Unable to parse {}: {}", manifest_path.display(), e))
+    }
+
+    /// Tools installed by default on the platform with their recommended version and archive.
+    pub fn get_required_tools(&self, platform: &str) -> Vec<(&Tool, &ToolVersion, &Download)> {
+        self.tools.iter()
+            .filter(|tool| tool.get_install(platform) == "always")
+            .filter_map(|tool| {
+                let version = tool.get_recommended_version()?;
+                Some((tool, version, version.get_download(platform)?))
+            })
+            .collect()
+    }
+}
+
+/// Platform key of tools.json for Rust host triple, the same mapping as idf_tools.py uses.
+pub fn get_platform(host_triple: &str) -> Option<&'static str> {
+    let arch = host_triple.split('-').next()?;
+    if host_triple.contains("windows") {
+        return match arch {
+            "x86_64" => Some("win64"),
+            "i686" | "i586" => Some("win32"),
+            _ => None,
+        };
+    }
+    if host_triple.contains("apple-darwin") {
+        return match arch {
+            "x86_64" => Some("macos"),
+            "aarch64" => Some("macos-arm64"),
+            _ => None,
+        };
+    }
+    if host_triple.contains("linux") {
+        return match arch {
+            "x86_64" => Some("linux-amd64"),
+            "aarch64" => Some("linux-arm64"),
+            "i686" | "i586" => Some("linux-i686"),
+            _ if arch.starts_with("arm") && host_triple.ends_with("hf") => Some("linux-armhf"),
+            _ if arch.starts_with("arm") => Some("linux-armel"),
+            _ => None,
+        };
+    }
+    None
+}
+
+/// Installation directory of tool version, <IDF_TOOLS_PATH>/tools/<name>/<version> like idf_tools.py uses.
+pub fn get_tool_directory(tool: &Tool, version: &ToolVersion) -> String {
+    get_tool_path(format!("{}/{}", tool.name, version.name))
+}
+
+/// Download recommended versions of tools required on the platform, verify their SHA-256 and extract them.
+/// Versions installed before are kept. Export paths and variables of the tools are applied to the user
+/// environment and recorded in receipts, so that uninstall reverts them.
+pub fn install_tools(manifest: &ToolsManifest, platform: &str, parallelism: usize) -> Result<(), PackageError> {
+    let mut scheduler = Scheduler::new(parallelism);
+    let mut total_size = 0;
+    for (tool, version, download) in manifest.get_required_tools(platform) {
+        let tool_directory = get_tool_directory(tool, version);
+        if is_installed(Path::new(&tool_directory)) {
+            println!("Using installed {} {}", tool.name, version.name);
+            continue;
+        }
+        total_size += download.size;
+        let archive = get_dist_path(download.get_file_name());
+        let extracted_archive = archive.clone();
+        scheduler.add(&tool.name, &download.url, &archive, download.get_sha256(), &[], move || {
+            extract_staged(Path::new(&tool_directory), &extracted_archive, |staging_path| {
+                extract_archive_strip_components(Path::new(&extracted_archive), staging_path, tool.strip_container_dirs)
+            })?;
+            receipt::record(Receipt::new("idf", &tool.name, &version.name, &download.url)
+                .with_archive(&extracted_archive)
+                .with_path(&tool_directory));
+            Ok(())
+        });
+    }
+    if total_size > 0 {
+        println!("Installing tools for {}, {} to download", platform, format_bytes(total_size));
+    }
+    scheduler.run()?;
+
+    for (tool, version, _download) in manifest.get_required_tools(platform) {
+        let tool_directory = get_tool_directory(tool, version);
+        for export_directory in tool.get_export_directories(platform, &tool_directory) {
+            println!("{} {}: PATH+={}", tool.name, version.name, export_directory);
+            receipt::update_env_path("idf", &tool.name, &export_directory);
+        }
+        for (name, value) in tool.get_export_vars(&tool_directory) {
+            println!("{} {}: {}={}", tool.name, version.name, name, value);
+            receipt::set_env_variable("idf", &tool.name, &name, &value);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOOLS_JSON: &str = r#"{
+      "version": 1,
+      "tools": [
+        {
+          "name": "xtensa-esp-elf",
+          "description": "Toolchain for 32-bit Xtensa based on GCC",
+          "export_paths": [["xtensa-esp-elf", "bin"]],
+          "export_vars": {"XTENSA_GNU_CONFIG": "${TOOL_PATH}/xtensa-esp-elf/lib/xtensa_esp32.so"},
+          "install": "always",
+          "supported_targets": ["esp32", "esp32s2", "esp32s3"],
+          "versions": [
+            {
+              "name": "esp-12.2.0_20230208",
+              "status": "recommended",
+              "linux-amd64": {"sha256": "aaaa", "size": 1000, "url": "https://github.com/espressif/crosstool-NG/releases/download/esp-12.2.0_20230208/xtensa-esp-elf-12.2.0_20230208-x86_64-linux-gnu.tar.xz"},
+              "macos": {"sha256": "bbbb", "size": 2000, "url": "https://github.com/espressif/crosstool-NG/releases/download/esp-12.2.0_20230208/xtensa-esp-elf-12.2.0_20230208-x86_64-apple-darwin.tar.xz"}
+            },
+            {"name": "esp-2021r2", "status": "supported", "linux-amd64": {"sha256": "cccc", "size": 900, "url": "https://example.com/old.tar.xz"}}
+          ]
+        },
+        {
+          "name": "idf-exe",
+          "install": "never",
+          "platform_overrides": [{"install": "always", "platforms": ["win64"], "export_paths": [[""]]}],
+          "strip_container_dirs": 1,
+          "versions": [{"name": "1.0.3", "status": "recommended", "any": {"sha256": "", "size": 10, "url": "https://dl.espressif.com/dl/idf-exe-v1.0.3.zip"}}]
+        }
+      ]
+    }"#;
+
+    #[test]
+    fn test_parse_manifest() {
+        let manifest = ToolsManifest::parse(TOOLS_JSON).unwrap();
+        assert_eq!(manifest.tools.len(), 2);
+        let toolchain = &manifest.tools[0];
+        assert_eq!(toolchain.get_export_paths("linux-amd64"), &[vec!["xtensa-esp-elf".to_string(), "bin".to_string()]]);
+        assert_eq!(toolchain.versions[0].downloads["linux-amd64"].size, 1000);
+
+        let required = manifest.get_required_tools("linux-amd64");
+        assert_eq!(required.len(), 1);
+        assert_eq!(required[0].1.name, "esp-12.2.0_20230208");
+        assert_eq!(required[0].2.sha256, "aaaa");
+        assert_eq!(required[0].2.get_file_name(), "xtensa-esp-elf-12.2.0_20230208-x86_64-linux-gnu.tar.xz");
+        // Intel build is used on Apple Silicon, tool without archive for the platform is not required
+        assert_eq!(manifest.get_required_tools("macos-arm64")[0].2.sha256, "bbbb");
+        assert!(manifest.get_required_tools("linux-arm64").is_empty());
+
+        let win64: Vec<&str> = manifest.get_required_tools("win64").iter().map(|(tool, _, _)| tool.name.as_str()).collect();
+        assert_eq!(win64, vec!["idf-exe"]);
+        assert_eq!(manifest.tools[1].strip_container_dirs, 1);
+        assert_eq!(manifest.get_required_tools("win64")[0].2.get_sha256(), None);
+        assert_eq!(required[0].2.get_sha256(), Some("aaaa"));
+
+        let tools_path = Path::new("tools").join("xtensa-esp-elf").join("esp-12.2.0_20230208");
+        let tool_directory = tools_path.display().to_string();
+        assert_eq!(toolchain.get_export_directories("linux-amd64", &tool_directory),
+                   vec![tools_path.join("xtensa-esp-elf").join("bin").display().to_string()]);
+        assert_eq!(manifest.tools[1].get_export_directories("win64", "idf-exe"), vec!["idf-exe".to_string()]);
+        assert_eq!(toolchain.get_export_vars(&tool_directory),
+                   vec![("XTENSA_GNU_CONFIG".to_string(), format!("{}/xtensa-esp-elf/lib/xtensa_esp32.so", tool_directory))]);
+        assert!(ToolsManifest::parse("{}").is_err());
+    }
+
+    #[test]
+    fn test_get_platform() {
+        assert_eq!(get_platform("x86_64-unknown-linux-gnu"), Some("linux-amd64"));
+        assert_eq!(get_platform("aarch64-unknown-linux-gnu"), Some("linux-arm64"));
+        assert_eq!(get_platform("armv7-unknown-linux-gnueabihf"), Some("linux-armhf"));
+        assert_eq!(get_platform("arm-unknown-linux-gnueabi"), Some("linux-armel"));
+        assert_eq!(get_platform("x86_64-pc-windows-msvc"), Some("win64"));
+        assert_eq!(get_platform("i686-pc-windows-gnu"), Some("win32"));
+        assert_eq!(get_platform("aarch64-apple-darwin"), Some("macos-arm64"));
+        assert_eq!(get_platform("riscv64gc-unknown-freebsd"), None);
+    }
+}
@@ -1,11 +1,13 @@
 extern crate json;
 extern crate clap;
 
+use clap::Arg;
 use clap_nested::{Commander};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
 
 mod antivirus;
+mod cache;
 mod config;
 mod companion;
 mod driver;
@@ -13,6 +15,7 @@ mod ide;
 mod idf;
 mod launcher;
 mod package;
+mod receipt;
 mod rust;
 mod shell;
 mod certificate;
@@ -25,10 +28,34 @@ async fn app() -> Result<()> {
                 .name("idf-env")
                 .author("Espressif Systems - https://www.espressif.com")
                 .about("Tool for maintaining ESP-IDF environment on computer.")
+                .arg(
+                    Arg::with_name("offline")
+                        .long("offline")
+                        .help("Never download, use archives from dist directory and offline bundle only")
+                )
+                .arg(
+                    Arg::with_name("offline-bundle")
+                        .long("offline-bundle")
+                        .help("Directory with pre-downloaded archives")
+                        .takes_value(true)
+                )
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("dry-run")
+                        .help("Print archives required by the command and whether they are available, then stop")
+                )
 
         })
-        .args(|_args, matches| matches.value_of("environment").unwrap_or("dev"))
+        .args(|_args, matches| {
+            package::settings::set_command_line(package::settings::CommandLine {
+                offline: matches.is_present("offline"),
+                bundle_path: matches.value_of("offline-bundle").map(|path| path.to_string()),
+                dry_run: matches.is_present("dry-run"),
+            });
+            matches.value_of("environment").unwrap_or("dev")
+        })
         .add_cmd(antivirus::get_multi_cmd())
+        .add_cmd(cache::get_multi_cmd())
         .add_cmd(certificate::get_multi_cmd())
         .add_cmd(companion::get_multi_cmd())
         .add_cmd(config::get_multi_cmd())
@@ -36,6 +63,7 @@ async fn app() -> Result<()> {
         .add_cmd(ide::get_multi_cmd())
         .add_cmd(idf::get_multi_cmd())
         .add_cmd(launcher::get_multi_cmd())
+        .add_cmd(receipt::get_multi_cmd())
         .add_cmd(rust::get_multi_cmd())
         .add_cmd(shell::get_multi_cmd())
         .add_cmd(toit::get_multi_cmd())
@@ -2,7 +2,7 @@ use clap::Arg;
 use clap_nested::{Command, Commander, MultiCommand};
 
 use std::{env, fs};
-use crate::config::{get_tools_path, get_idf_id};
+use crate::config::{get_tools_path, get_idf_id, get_idf_path_from_matches, load_config_or_exit};
 
 fn get_windows_terminal_fragments_path(title: &str) -> String {
     let local_app_data = env::var("LocalAppData").unwrap();
@@ -16,10 +16,13 @@ fn get_powershell_path() -> String {
 
 fn get_add_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
     let title = matches.value_of("title").unwrap();
-    let idf_path = matches.value_of("idf-path").unwrap();
+    let idf_path = get_idf_path_from_matches(matches);
     let fragments_path = get_windows_terminal_fragments_path(title);
     let tools_path = get_tools_path();
-    let idf_id = get_idf_id(idf_path);
+    let idf_id = get_idf_id(&idf_path);
+    if load_config_or_exit().get_installation(&idf_id).is_none() {
+        println!("Warning: ESP-IDF in {} is not registered in configuration ({})", idf_path, idf_id);
+    }
 
     // After fresh installation of Windows Terminal the fragment path does not exist.
     // Microsoft recommends to create one
@@ -79,6 +82,13 @@ pub fn get_add_cmd<'a>() -> Command<'a, str> {
                         .takes_value(true)
                         .help("Path to ESP-IDF"),
                 )
+                .arg(
+                    Arg::with_name("name")
+                        .short("n")
+                        .long("name")
+                        .takes_value(true)
+                        .help("Custom name of registered ESP-IDF installation"),
+                )
         })
         .runner(|_args, matches| get_add_runner(_args, matches)
         )
@@ -1,342 +1,437 @@
+pub mod checksum;
+pub mod error;
+pub mod extract;
+pub mod mirror;
+pub mod offline;
+pub mod progress;
+mod resume;
+mod retry;
+pub mod scheduler;
+pub mod settings;
+pub mod signature;
+pub mod staging;
+
 use anyhow::Context;
 use std::{fs, io};
 use std::path::Path;
-use std::io::Cursor;
+use std::io::Write;
 use std::fs::File;
-use std::path::PathBuf;
-use tar::Archive;
-use flate2::read::GzDecoder;
-use xz2::read::XzDecoder;
+use std::sync::Arc;
 
 use tokio::runtime::Handle;
 use crate::config::{ get_dist_path, get_tool_path };
+pub use error::PackageError;
+use progress::CombinedProgress;
+use retry::RetryPolicy;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
-
-pub fn unzip(file_path: String, output_directory: String) -> Result<()> {
-    let file_name = std::path::Path::new(&file_path);
-    let file = fs::File::open(&file_name).unwrap();
-
-    let mut archive = zip::ZipArchive::new(file).unwrap();
-
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let file_outpath = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
-        };
-
-        // Add path prefix to extract the file
-        let mut outpath = std::path::PathBuf::new();
-        outpath.push(&output_directory);
-        outpath.push(file_outpath);
-
-        {
-            let comment = file.comment();
-            if !comment.is_empty() {
-                println!("File {} comment: {}", i, comment);
-            }
-        }
-
-        if (&*file.name()).ends_with('/') {
-            println!("* extracted: \"{}\"", outpath.display());
-            fs::create_dir_all(&outpath).unwrap();
-        } else {
-            println!(
-                "* extracted: \"{}\" ({} bytes)",
-                outpath.display(),
-                file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(&p).unwrap();
-                }
+type PackageResult<T> = std::result::Result<T, PackageError>;
+
+/// Stream response to a temporary .part file next to the output and rename it when complete,
+/// so that interrupted download never looks like a cached archive. Partial response is appended
+/// to the data downloaded by previous attempt.
+async fn stream_to_file(url: &str, mut response: reqwest::Response, output: &str, offset: u64,
+                        combined: Option<Arc<CombinedProgress>>) -> PackageResult<()> {
+    let part_path = resume::get_part_path(output);
+    let file_name = Path::new(output).file_name().unwrap_or_default().to_string_lossy().to_string();
+    let total = response.content_length().map(|length| length + offset);
+    let mut progress = progress::DownloadProgress::start(url, &file_name, total, offset, progress::get_progress_mode(), combined);
+    let file = if offset > 0 {
+        fs::OpenOptions::new().append(true).open(&part_path)?
+    } else {
+        File::create(&part_path)?
+    };
+    let mut file = io::BufWriter::new(file);
+    loop {
+        match response.chunk().await {
+            Ok(Some(chunk)) => {
+                file.write_all(&chunk)?;
+                progress.advance(chunk.len() as u64);
+            },
+            Ok(None) => break,
+            Err(e) => {
+                file.flush()?;
+                progress.fail(&e.to_string());
+                return Err(PackageError::network(format!("Download of {} interrupted: {}", url, e)));
             }
-            let mut outfile = fs::File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
         }
     }
+    file.flush()?;
+    drop(file);
+    fs::rename(&part_path, output)?;
+    resume::discard(output);
+    progress.finish();
     Ok(())
 }
 
-pub fn unzip_strip_prefix(file_path: String, output_directory: String, strip_prefix: &str) -> Result<()> {
-    let file_name = std::path::Path::new(&file_path);
-    let file = fs::File::open(&file_name).unwrap();
-
-    let mut archive = zip::ZipArchive::new(file).unwrap();
-
-    for i in 0..archive.len() {
-        let mut file = archive.by_index(i).unwrap();
-        let file_outpath = match file.enclosed_name() {
-            Some(path) => path.to_owned(),
-            None => continue,
-        };
-
-        // Add path prefix to extract the file
-        let mut outpath = std::path::PathBuf::new();
-        outpath.push(&output_directory);
-
-        // Skip files in top level directories which are not under directory with prefix
-        if !file_outpath.starts_with(strip_prefix) {
-            println!("* skipped: \"{}\"", file_outpath.display());
-            continue;
-        }
-
-        let stripped_file_outpath = file_outpath.strip_prefix(strip_prefix).unwrap();
-        outpath.push(stripped_file_outpath);
+async fn fetch_url(url: String, output: String, combined: Option<Arc<CombinedProgress>>) -> PackageResult<()> {
+    let client = reqwest::Client::new();
+    let partial = resume::find_resumable(&output, &url);
+    if partial.is_none() {
+        resume::discard(&output);
+    }
 
-        {
-            let comment = file.comment();
-            if !comment.is_empty() {
-                println!("File {} comment: {}", i, comment);
-            }
-        }
+    let mut request = client.get(&url);
+    if let Some(partial) = &partial {
+        println!("Resuming download from byte {}", partial.offset);
+        request = request
+            .header(reqwest::header::RANGE, format!("bytes={}-", partial.offset))
+            .header(reqwest::header::IF_RANGE, partial.validator.as_str());
+    }
 
-        if (&*file.name()).ends_with('/') {
-            if !Path::new(file.name()).exists() {
-                println!("* created: \"{}\"", outpath.display());
-                fs::create_dir_all(&outpath).unwrap();
-            }
-        } else {
-            println!(
-                "* extracted: \"{}\" ({} bytes)",
-                outpath.display(),
-                file.size()
-            );
-            if let Some(p) = outpath.parent() {
-                if !p.exists() {
-                    fs::create_dir_all(&p).unwrap();
+    match request.send().await {
+        Ok(r) => {
+            let status = r.status();
+            if let Some(partial) = &partial {
+                if status == reqwest::StatusCode::PARTIAL_CONTENT && resume::get_content_range_start(r.headers()) == Some(partial.offset) {
+                    return stream_to_file(&url, r, &output, partial.offset, combined).await;
                 }
+                if status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE {
+                    // Partial file is not usable, start from scratch
+                    resume::discard(&output);
+                    return Box::pin(fetch_url(url, output, combined)).await;
+                }
+                // Server does not support ranges or the file has changed, full content follows
+                println!("Unable to resume download, downloading whole file");
             }
-            let mut outfile = fs::File::create(&outpath).unwrap();
-            io::copy(&mut file, &mut outfile).unwrap();
-        }
+            if !status.is_success() || status == reqwest::StatusCode::PARTIAL_CONTENT {
+                return Err(PackageError::http_status(&url, status.as_u16()));
+            }
+            resume::save_validator(&output, &url, r.headers());
+            stream_to_file(&url, r, &output, 0, combined).await
+        },
+        Err(e) => Err(PackageError::network(format!("Download of {} failed: {}", url, e))),
     }
-    Ok(())
-}
-
-pub fn untarxz_strip_prefix(file_path: String, output_directory: String, strip_prefix: &str) -> Result<()> {
-    let tar_xz = File::open(file_path)?;
-    let tar = XzDecoder::new(tar_xz);
-    let mut archive = Archive::new(tar);
-    archive.entries()?
-        .filter_map(|e| e.ok())
-        .map(|mut entry| -> Result<PathBuf> {
-            let path = entry.path()?.strip_prefix(strip_prefix)?.to_owned();
-            let full_path = format!("{}/{}", output_directory, path.display().to_string());
-            entry.unpack(&full_path)?;
-            Ok(full_path.parse().unwrap())
-        })
-        .filter_map(|e| e.ok())
-        .for_each(|x| println!("> {}", x.display()));
-    Ok(())
-}
-
-pub fn untarxz(file_path: String, output_directory: String) -> Result<()> {
-    let tar_xz = File::open(file_path)?;
-    let tar = XzDecoder::new(tar_xz);
-    let mut archive = Archive::new(tar);
-    archive.entries()?
-        .filter_map(|e| e.ok())
-        .map(|mut entry| -> Result<PathBuf> {
-            let path = entry.path()?.to_owned();
-            let full_path = format!("{}/{}", output_directory, path.display().to_string());
-            entry.unpack(&full_path)?;
-            Ok(full_path.parse().unwrap())
-        })
-        .filter_map(|e| e.ok())
-        .for_each(|x| println!("> {}", x.display()));
-    Ok(())
-}
-
-
-pub fn untargz_strip_prefix(file_path: String, output_directory: String, strip_prefix: &str) -> Result<()> {
-    let tar_gz = File::open(file_path)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive.entries()?
-        .filter_map(|e| e.ok())
-        .map(|mut entry| -> Result<PathBuf> {
-            let path = entry.path()?.strip_prefix(strip_prefix)?.to_owned();
-            let full_path = format!("{}/{}", output_directory, path.display().to_string());
-            entry.unpack(&full_path)?;
-            Ok(full_path.parse().unwrap())
-        })
-        .filter_map(|e| e.ok())
-        .for_each(|x| println!("> {}", x.display()));
-    Ok(())
-}
-
-pub fn untargz(file_path: String, output_directory: String) -> Result<()> {
-    let tar_gz = File::open(file_path)?;
-    let tar = GzDecoder::new(tar_gz);
-    let mut archive = Archive::new(tar);
-    archive.entries()?
-        .filter_map(|e| e.ok())
-        .map(|mut entry| -> Result<PathBuf> {
-            let path = entry.path()?.to_owned();
-            let full_path = format!("{}/{}", output_directory, path.display().to_string());
-            entry.unpack(&full_path)?;
-            Ok(full_path.parse().unwrap())
-        })
-        .filter_map(|e| e.ok())
-        .for_each(|x| println!("> {}", x.display()));
-    Ok(())
 }
 
-async fn fetch_url(url: String, output: String) -> Result<()> {
-    let response = reqwest::get(&url).await;
-    match response {
-        Ok(r) => {
-            let mut file = std::fs::File::create(output)?;
-            let mut content = Cursor::new(r.bytes().await?);
-            std::io::copy(&mut content, &mut file)?;
-
-            return Ok(())
-        },
-        _ => {
-            println!("Download of {} failed", url);
-            // Exit code is 0, there is temporal issue with Windows Installer which does not recover from error exit code
-            #[cfg(windows)]
-            std::process::exit(0);
-            #[cfg(unix)]
-            std::process::exit(1);
-        }
-    };
+/// Hashing reads the whole archive and manifest update waits for its lock, so verification runs on a blocking thread.
+async fn verify_blocking<F: FnOnce() -> PackageResult<()> + Send + 'static>(verify: F) -> PackageResult<()> {
+    tokio::task::spawn_blocking(verify).await
+        .map_err(|e| PackageError::Disk(format!("Verification failed: {}", e)))?
 }
 
-async fn download_zip(url: String, output: String) -> Result<()> {
+/// Download file unless it's cached. Cached and downloaded files are verified against expected SHA-256,
+/// shipped digest of the URL or digest recorded by previous download, file which does not match is removed.
+/// Transient failures are retried according to the policy, each retry resumes the partial download.
+/// Archive found in the offline bundle is copied instead of downloading, offline mode never touches network.
+/// URL is rewritten by mirror rules before the download.
+async fn download_zip(url: String, output: String, sha256: Option<String>, policy: RetryPolicy,
+                      combined: Option<Arc<CombinedProgress>>) -> PackageResult<()> {
+    let sha256 = checksum::get_expected_digest(&url, sha256.as_deref());
+    let (file_path, expected_sha256) = (output.clone(), sha256.clone());
+    let verify_download = move || checksum::verify_download(Path::new(&file_path), expected_sha256.as_deref());
     if Path::new(&output).exists() {
-        println!("Using cached archive: {}", output);
-        return Ok(());
+        let (file_path, expected_sha256) = (output.clone(), sha256.clone());
+        match verify_blocking(move || checksum::verify_cached(Path::new(&file_path), expected_sha256.as_deref())).await {
+            Ok(_) => {
+                println!("Using cached archive: {}", output);
+                return Ok(());
+            },
+            Err(e) => println!("{}", e),
+        }
+    }
+    if offline::copy_from_bundle(&output)? {
+        return verify_blocking(verify_download).await;
     }
+    if offline::is_offline() {
+        return Err(PackageError::Unavailable(format!("Offline mode: {} is not cached, download from {} is not allowed", output, url)));
+    }
+    let url = mirror::resolve_url(&url);
     println!("Downloading {} to {}", url, output);
-    fetch_url(url, output).await
+    let mut attempt = 0;
+    loop {
+        match fetch_url(url.clone(), output.clone(), combined.clone()).await {
+            Ok(_) => break,
+            Err(e) if e.is_transient() && attempt < policy.retries => {
+                let delay = policy.get_delay(attempt);
+                attempt += 1;
+                println!("{}. Retrying in {} ms ({}/{})", e, delay.as_millis(), attempt, policy.retries);
+                tokio::time::sleep(delay).await;
+            },
+            Err(e) => return Err(e),
+        }
+    }
+    verify_blocking(verify_download).await
 }
 
-pub fn download_package(package_url: String, package_archive: String) -> Result<()> {
+fn download_package_with_policy(package_url: String, package_archive: String, sha256: Option<&str>, policy: RetryPolicy) -> PackageResult<()> {
     let handle = Handle::current().clone();
+    let sha256 = sha256.map(|digest| digest.to_string());
     let th = std::thread::spawn(move || {
-        handle.block_on(download_zip(package_url, package_archive)).unwrap();
+        handle.block_on(download_zip(package_url, package_archive, sha256, policy, None))
     });
-    Ok(th.join().unwrap())
+    th.join().unwrap()
 }
 
-pub fn prepare_package(package_url: String, package_archive: &str, output_directory: String) -> Result<()> {
-    if Path::new(&output_directory).exists() {
-        println!("Using cached directory: {}", output_directory);
-        return Ok(());
-    }
+pub fn download_package(package_url: String, package_archive: String, sha256: Option<&str>) -> PackageResult<()> {
+    offline::check_artifacts(&[offline::Artifact { url: package_url.clone(), archive: package_archive.clone() }])?;
+    download_package_with_policy(package_url, package_archive, sha256, RetryPolicy::from_env())
+}
 
+fn create_dist_directory() -> PackageResult<()> {
     let dist_path = get_dist_path("");
     if !Path::new(&dist_path).exists() {
         println!("Creating dist directory: {}", dist_path);
-        match fs::create_dir_all(&dist_path)  {
-            Ok(_) => { println!("Ok"); },
-            Err(_e) => { println!("Failed");}
-        }
+        fs::create_dir_all(&dist_path)
+            .map_err(|e| PackageError::Disk(format!("Unable to create {}: {}", dist_path, e)))?;
     }
+    Ok(())
+}
+
+/// Download detached signature of the archive and verify the archive by trusted keys.
+/// Archive and signature which do not verify are removed from the cache. Signature which can't be
+/// downloaded, e.g. it's not published, fails like a signature which does not verify.
+fn verify_signature(package_url: &str, package_archive: &str, keys: &[minisign_verify::PublicKey]) -> PackageResult<()> {
+    let artifact = signature::get_signature_artifact(package_url, package_archive);
+    download_package(artifact.url.clone(), artifact.archive.clone(), None).map_err(|e| match e {
+        PackageError::Network { .. } => PackageError::Signature(format!("Unable to download signature of {}: {}", package_archive, e)),
+        e => e,
+    })?;
+    signature::verify_file(Path::new(package_archive), Path::new(&artifact.archive), keys).inspect_err(|_e| {
+        let _ = checksum::evict(Path::new(package_archive));
+        let _ = checksum::evict(Path::new(&artifact.archive));
+    })
+}
+
+/// Download the archive and extract it into output directory, optionally only the content of strip_prefix
+/// directory. Directory with completion marker is reused, see staging::extract_staged.
+/// Signed archive hosted by Espressif is extracted only when its detached signature is made by a trusted key,
+/// the signature is not checked when no key is trusted.
+fn prepare_archive(package_url: &str, package_archive: &str, output_directory: &str, strip_prefix: Option<&str>, sha256: Option<&str>, signed: bool) -> PackageResult<()> {
+    if staging::is_installed(Path::new(output_directory)) {
+        println!("Using cached directory: {}", output_directory);
+        return Ok(());
+    }
+
+    create_dist_directory()?;
 
     let package_archive = get_dist_path(package_archive);
+    let keys = if signed { signature::get_verification_keys(package_url)? } else { Vec::new() };
+    if signed && keys.is_empty() && signature::is_signed_host(package_url) {
+        println!("No trusted signing key, signature of {} is not verified", package_archive);
+    }
+    if !keys.is_empty() {
+        // Dry run and offline mode report the signature together with the archive
+        offline::check_artifacts(&[
+            offline::Artifact { url: package_url.to_string(), archive: package_archive.clone() },
+            signature::get_signature_artifact(package_url, &package_archive),
+        ])?;
+    }
 
-    match download_package(package_url, package_archive.clone()) {
-        Ok(_) => { println!("Download ok"); },
-        Err(_e) => { println!("Download failed");}
+    download_package(package_url.to_string(), package_archive.clone(), sha256)?;
+    println!("Download ok");
+    if !keys.is_empty() {
+        verify_signature(package_url, &package_archive, &keys)?;
+        println!("Signature ok");
     }
 
+    extract_package(&package_archive, output_directory, strip_prefix)
+}
+
+fn extract_package(package_archive: &str, output_directory: &str, strip_prefix: Option<&str>) -> PackageResult<()> {
     println!("Extracting to {}", output_directory);
-    let extension = Path::new(package_archive.as_str()).extension().unwrap().to_str().unwrap();
-    match extension {
-        "zip" => {
-            unzip(package_archive, output_directory).unwrap();
-        }
-        "gz" => {
-            match fs::create_dir_all(&output_directory)  {
-                Ok(_) => { println!("Creating {} - Ok", output_directory); },
-                Err(_e) => { println!("Creating {} - Failed", output_directory);}
-            }
-            untargz(package_archive, output_directory).unwrap();
-        }
-        "xz" => {
-            untarxz(package_archive, output_directory).unwrap();
-        }
-        _ => { println!("Unsuported file extension."); }
+    staging::extract_staged(Path::new(output_directory), package_archive, |staging_path| {
+        extract::extract_archive(Path::new(package_archive), staging_path, strip_prefix)
+    })
+}
+
+/// Extract archive which was already downloaded and verified by Scheduler, so that it's not hashed again.
+fn prepare_downloaded_archive(package_archive: &str, output_directory: &str, strip_prefix: Option<&str>) -> PackageResult<()> {
+    if staging::is_installed(Path::new(output_directory)) {
+        println!("Using cached directory: {}", output_directory);
+        return Ok(());
     }
+    extract_package(&get_dist_path(package_archive), output_directory, strip_prefix)
+}
 
-    Ok(())
+pub fn prepare_package(package_url: String, package_archive: &str, output_directory: String, sha256: Option<&str>) -> PackageResult<()> {
+    prepare_archive(&package_url, package_archive, &output_directory, None, sha256, false)
+}
+
+#[cfg(windows)]
+pub fn prepare_signed_package(package_url: String, package_archive: &str, output_directory: String, sha256: Option<&str>) -> PackageResult<()> {
+    prepare_archive(&package_url, package_archive, &output_directory, None, sha256, true)
+}
+
+pub fn prepare_downloaded_package(package_archive: &str, output_directory: String) -> PackageResult<()> {
+    prepare_downloaded_archive(package_archive, &output_directory, None)
 }
 
-pub fn prepare_single_binary(package_url: &str, binary_name: &str, output_directory: &str) -> String {
+pub fn prepare_single_binary(package_url: &str, binary_name: &str, output_directory: &str, sha256: Option<&str>) -> PackageResult<String> {
     let tool_path = get_tool_path(output_directory.to_string());
     let binary_path = format!("{}/{}", tool_path, binary_name);
 
-    if Path::new(&binary_path).exists() {
+    let expected_sha256 = checksum::get_expected_digest(package_url, sha256);
+    if Path::new(&binary_path).exists() && checksum::verify_cached(Path::new(&binary_path), expected_sha256.as_deref()).is_ok() {
         println!("Using cached tool: {}", binary_path);
-        return binary_path;
+        return Ok(binary_path);
     }
 
     if !Path::new(&tool_path).exists() {
         println!("Creating tool directory: {}", tool_path);
-        match fs::create_dir_all(&tool_path) {
-            Ok(_) => { println!("Ok"); },
-            Err(_e) => { println!("Failed");}
-        }
+        fs::create_dir_all(&tool_path)
+            .map_err(|e| PackageError::Disk(format!("Unable to create {}: {}", tool_path, e)))?;
     }
 
-    match download_package(package_url.to_string(), binary_path.to_string()) {
-        Ok(_) => { println!("Ok"); },
-        Err(_e) => { println!("Failed");}
+    download_package(package_url.to_string(), binary_path.to_string(), sha256)?;
+    println!("Ok");
+    Ok(binary_path)
+}
+
+pub fn prepare_signed_package_strip_prefix(package_url: &str, package_archive: &str, output_directory: String, strip_prefix: &str, sha256: Option<&str>) -> PackageResult<()> {
+    prepare_archive(package_url, package_archive, &output_directory, Some(strip_prefix), sha256, true)
+}
+
+pub fn prepare_downloaded_package_strip_prefix(package_archive: &str, output_directory: String, strip_prefix: &str) -> PackageResult<()> {
+    prepare_downloaded_archive(package_archive, &output_directory, Some(strip_prefix))
+}
+
+pub fn remove_package(package_archive: &str, output_directory: &str) -> Result<()> {
+    if Path::new(package_archive).exists() {
+        fs::remove_file(package_archive).with_context(|| format!("Unable to delete `{}`", package_archive))?;
+    }
+    if Path::new(output_directory).exists() {
+        fs::remove_dir_all(output_directory).with_context(|| format!("Unable to delete `{}`", output_directory))?;
     }
-    return binary_path;
+    Ok(())
 }
 
-pub fn prepare_package_strip_prefix(package_url: &str, package_archive: &str, output_directory: String, strip_prefix: &str) -> Result<()> {
-    if Path::new(&output_directory).exists() {
-        println!("Using cached directory: {}", output_directory);
-        return Ok(());
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+
+    pub(super) type Responder = Box<dyn Fn(&str) -> Vec<u8> + Send>;
+
+    /// Local HTTP server stand-in. Each connection is answered by the next responder,
+    /// which receives lowercase request head and returns raw response.
+    pub(super) fn serve(responders: Vec<Responder>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for responder in responders {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request = String::new();
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    request.push_str(&line.to_lowercase());
+                    line.clear();
+                }
+                let _ = stream.write_all(&responder(&request));
+            }
+        });
+        format!("http://{}", address)
     }
 
-    let dist_path = get_dist_path("");
-    if !Path::new(&dist_path).exists() {
-        println!("Creating dist directory: {}", dist_path);
-        match fs::create_dir_all(&dist_path) {
-            Ok(_) => { println!("Ok"); },
-            Err(_e) => { println!("Failed");}
-        }
+    pub(super) fn get_body() -> Vec<u8> {
+        (0..200_000u32).map(|i| (i % 251) as u8).collect()
     }
 
-    let package_archive = get_dist_path(package_archive);
+    /// Response which announces whole body but the connection drops after `sent` bytes.
+    fn respond_interrupted(sent: usize) -> Responder {
+        Box::new(move |_request| {
+            let body = get_body();
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+            response.extend_from_slice(&body[..sent]);
+            response
+        })
+    }
 
-    match download_package(package_url.to_string(), package_archive.to_string()) {
-        Ok(_) => { println!("Downloaded"); },
-        Err(_e) => { println!("Unable to download package"); }
+    pub(super) fn respond_full() -> Responder {
+        Box::new(|_request| {
+            let body = get_body();
+            let mut response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n", body.len()).into_bytes();
+            response.extend_from_slice(&body);
+            response
+        })
     }
-    if !Path::new(&output_directory).exists() {
-        let package_archive = package_archive.to_string();
-        let extension = Path::new(package_archive.as_str()).extension().unwrap().to_str().unwrap();
 
-        match extension {
-            "zip" => {
-                unzip_strip_prefix(package_archive, output_directory, strip_prefix).unwrap();
-            }
-            "gz" => {
-                untargz_strip_prefix(package_archive, output_directory, strip_prefix).unwrap();
-            }
-            "xz" => {
-                untarxz_strip_prefix(package_archive, output_directory, strip_prefix).unwrap();
-            }
-            _ => { println!("Unsuported file extension."); }
-        }
+    fn respond_range() -> Responder {
+        Box::new(|request| {
+            assert!(request.contains("if-range: \"v1\""));
+            let offset: usize = request.split("range: bytes=").nth(1).unwrap().split('-').next().unwrap().parse().unwrap();
+            let body = get_body();
+            let mut response = format!("HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\nContent-Range: bytes {}-{}/{}\r\nETag: \"v1\"\r\nConnection: close\r\n\r\n",
+                                       body.len() - offset, offset, body.len() - 1, body.len()).into_bytes();
+            response.extend_from_slice(&body[offset..]);
+            response
+        })
     }
-    Ok(())
-}
 
-pub fn remove_package(package_archive: &str, output_directory: &str) -> Result<()> {
-    if Path::new(package_archive).exists() {
-        fs::remove_file(package_archive).with_context(|| format!("Unable to delete `{}`", package_archive))?;
+    pub(super) fn get_output(test_name: &str) -> (PathBuf, String) {
+        let directory = std::env::temp_dir().join(format!("idf-env-{}-{}", test_name, std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("archive.zip").display().to_string();
+        (directory, output)
     }
-    if Path::new(output_directory).exists() {
-        fs::remove_dir_all(output_directory).with_context(|| format!("Unable to delete `{}`", output_directory))?;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_download_streams_to_file() {
+        let (directory, output) = get_output("download");
+        let url = format!("{}/archive.zip", serve(vec![respond_full()]));
+        download_package(url, output.clone(), None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), get_body());
+        assert!(!Path::new(&resume::get_part_path(&output)).exists());
+        assert!(checksum::get_recorded_digest(Path::new(&output)).is_some());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    const NO_RETRY: RetryPolicy = RetryPolicy { retries: 0, base_delay: std::time::Duration::from_millis(0) };
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_download_resumes_with_range() {
+        let (directory, output) = get_output("resume");
+        let url = format!("{}/archive.zip", serve(vec![respond_interrupted(70_000), respond_range()]));
+        assert!(download_package_with_policy(url.clone(), output.clone(), None, NO_RETRY).is_err());
+        assert!(!Path::new(&output).exists());
+        assert!(fs::metadata(resume::get_part_path(&output)).unwrap().len() > 0);
+
+        download_package(url, output.clone(), None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), get_body());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_download_restarts_when_range_is_ignored() {
+        let (directory, output) = get_output("restart");
+        let url = format!("{}/archive.zip", serve(vec![respond_interrupted(70_000), respond_full()]));
+        assert!(download_package_with_policy(url.clone(), output.clone(), None, NO_RETRY).is_err());
+        download_package(url, output.clone(), None).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), get_body());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_download_fails_on_shipped_digest_mismatch() {
+        let (directory, output) = get_output("shipped-digest");
+        let url = format!("{}/archive.zip", serve(vec![respond_full()]));
+        let shipped = [(url.as_str(), "0000000000000000000000000000000000000000000000000000000000000000")];
+        let error = download_package(url.clone(), output.clone(), checksum::find_digest(&shipped, &url)).unwrap_err();
+        assert_eq!(error.exit_code(), error::EXIT_CHECKSUM);
+        assert!(!Path::new(&output).exists());
+        assert_eq!(checksum::get_recorded_digest(Path::new(&output)), None);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    pub(super) fn respond_status(status: &'static str) -> Responder {
+        Box::new(move |_request| format!("HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n", status).into_bytes())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_download_retries_transient_errors() {
+        let (directory, output) = get_output("retry");
+        let policy = RetryPolicy { retries: 3, base_delay: std::time::Duration::from_millis(10) };
+        let url = format!("{}/archive.zip", serve(vec![respond_status("503 Service Unavailable"), respond_interrupted(70_000), respond_range()]));
+        download_package_with_policy(url, output.clone(), None, policy).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), get_body());
+
+        let url = format!("{}/missing.zip", serve(vec![respond_status("404 Not Found"), respond_full()]));
+        let error = download_package_with_policy(url, directory.join("missing.zip").display().to_string(), None, policy).unwrap_err();
+        assert!(!error.is_transient());
+        assert_eq!(error.exit_code(), error::EXIT_NETWORK);
+        fs::remove_dir_all(directory).unwrap();
     }
-    Ok(())
 }
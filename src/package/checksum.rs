@@ -0,0 +1,244 @@
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
+use std::sync::Mutex;
+
+use crate::config::storage::{get_temp_path, ConfigLock};
+use crate::package::error::PackageError;
+
+type Result<T> = std::result::Result<T, PackageError>;
+
+/// Digests of downloaded files are recorded in this file in the same directory,
+/// the format is compatible with `sha256sum -c`.
+pub const MANIFEST_NAME: &str = "SHA256SUMS";
+
+/// Expected SHA-256 of artifacts with pinned version, keyed by the original URL, before mirror rules apply.
+/// Artifacts listed here are verified also on the first download, others are trusted on first use and later
+/// verified against the digest recorded in the manifest. Artifacts downloaded from `latest` URLs change
+/// with each release, so they can't be listed. Digests are taken from SHA256SUMS published with the release.
+const SHIPPED_DIGESTS: &[(&str, &str)] = &[];
+
+/// Concurrent downloads of this process update the manifest one by one,
+/// the lock file next to the manifest serializes updates among processes.
+static MANIFEST_MUTEX: Mutex<()> = Mutex::new(());
+
+/// Manifest and its lock and temporary files.
+pub fn is_manifest_file(file_name: &str) -> bool {
+    file_name == MANIFEST_NAME || file_name.starts_with(&format!("{}.", MANIFEST_NAME))
+}
+
+pub fn compute_sha256(file_path: &Path) -> Result<String> {
+    let mut file = File::open(file_path)
+        .map_err(|e| PackageError::Disk(format!("Unable to open {}: {}", file_path.display(), e)))?;
+    let mut hasher = Sha256::new();
+    io::copy(&mut file, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Find digest of the URL in the table of shipped digests.
+pub fn find_digest<'a>(digests: &[(&str, &'a str)], url: &str) -> Option<&'a str> {
+    digests.iter().find(|(digest_url, _)| *digest_url == url).map(|(_, digest)| *digest)
+}
+
+/// Digest passed by the caller wins, otherwise the shipped digest of the URL is expected.
+pub fn get_expected_digest(url: &str, sha256: Option<&str>) -> Option<String> {
+    sha256.or_else(|| find_digest(SHIPPED_DIGESTS, url)).map(|digest| digest.to_string())
+}
+
+fn get_manifest_path(file_path: &Path) -> Option<std::path::PathBuf> {
+    Some(file_path.parent()?.join(MANIFEST_NAME))
+}
+
+fn get_file_name(file_path: &Path) -> String {
+    file_path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+/// Read manifest of a directory, map file name -> digest.
+pub fn read_manifest(directory: &Path) -> BTreeMap<String, String> {
+    let content = fs::read_to_string(directory.join(MANIFEST_NAME)).unwrap_or_default();
+    content.lines()
+        .filter_map(|line| {
+            let mut parts = line.splitn(2, char::is_whitespace);
+            let digest = parts.next()?.to_lowercase();
+            // sha256sum marks binary mode by asterisk in front of file name
+            let file_name = parts.next()?.trim_start().trim_start_matches('*');
+            if digest.is_empty() || file_name.is_empty() {
+                return None;
+            }
+            Some((file_name.to_string(), digest))
+        })
+        .collect()
+}
+
+fn write_manifest(directory: &Path, manifest: &BTreeMap<String, String>) -> Result<()> {
+    let manifest_path = directory.join(MANIFEST_NAME);
+    let temp_path = get_temp_path(&manifest_path.display().to_string());
+    let content: String = manifest.iter()
+        .map(|(file_name, digest)| format!("{}  {}\n", digest, file_name))
+        .collect();
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, &manifest_path).map_err(|e| {
+        let _ = fs::remove_file(&temp_path);
+        PackageError::Disk(format!("Unable to write {}: {}", manifest_path.display(), e))
+    })
+}
+
+fn update_manifest(file_path: &Path, digest: Option<String>) -> Result<()> {
+    let manifest_path = match get_manifest_path(file_path) {
+        Some(manifest_path) => manifest_path,
+        None => return Ok(()),
+    };
+    let directory = manifest_path.parent().unwrap();
+    let _guard = MANIFEST_MUTEX.lock().unwrap_or_else(|e| e.into_inner());
+    let _lock = ConfigLock::acquire(&manifest_path.display().to_string())
+        .map_err(|e| PackageError::Disk(e.to_string()))?;
+    let mut manifest = read_manifest(directory);
+    let file_name = get_file_name(file_path);
+    match digest {
+        Some(digest) => manifest.insert(file_name, digest),
+        None => manifest.remove(&file_name),
+    };
+    write_manifest(directory, &manifest)
+}
+
+pub fn get_recorded_digest(file_path: &Path) -> Option<String> {
+    let manifest_path = get_manifest_path(file_path)?;
+    read_manifest(manifest_path.parent()?).remove(&get_file_name(file_path))
+}
+
+/// Delete file and forget its recorded digest, so that it's downloaded again.
+pub fn evict(file_path: &Path) -> Result<()> {
+    if file_path.exists() {
+        fs::remove_file(file_path)
+            .map_err(|e| PackageError::Disk(format!("Unable to delete {}: {}", file_path.display(), e)))?;
+    }
+    update_manifest(file_path, None)
+}
+
+fn check_digest(file_path: &Path, expected_sha256: &str) -> Result<String> {
+    let digest = compute_sha256(file_path)?;
+    if digest != expected_sha256.to_lowercase() {
+        evict(file_path)?;
+        return Err(PackageError::Checksum(format!("Checksum mismatch of {}: expected SHA-256 {}, got {}. The file was removed.",
+                                                  file_path.display(), expected_sha256, digest)));
+    }
+    Ok(digest)
+}
+
+/// Validate file which exists in cache against expected digest, or against recorded digest
+/// when no digest is expected. File which does not match is evicted.
+pub fn verify_cached(file_path: &Path, expected_sha256: Option<&str>) -> Result<()> {
+    let expected_sha256 = match expected_sha256.map(|digest| digest.to_string()).or_else(|| get_recorded_digest(file_path)) {
+        Some(digest) => digest,
+        None => return Ok(()),
+    };
+    check_digest(file_path, &expected_sha256)?;
+    Ok(())
+}
+
+/// Validate freshly downloaded file and record its digest. File which does not match is evicted.
+pub fn verify_download(file_path: &Path, expected_sha256: Option<&str>) -> Result<()> {
+    let digest = match expected_sha256 {
+        Some(expected_sha256) => check_digest(file_path, expected_sha256)?,
+        None => compute_sha256(file_path)?,
+    };
+    update_manifest(file_path, Some(digest))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CacheStatus {
+    Valid,
+    Mismatch(String),
+    Unrecorded,
+    Missing,
+}
+
+/// Compare all files in directory with its manifest. Files listed in manifest which do not exist are reported as missing.
+pub fn verify_directory(directory: &Path) -> Result<Vec<(String, CacheStatus)>> {
+    let manifest = read_manifest(directory);
+    let mut result = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let entry = entry?;
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !entry.file_type()?.is_file() || is_manifest_file(&file_name) {
+            continue;
+        }
+        let status = match manifest.get(&file_name) {
+            Some(recorded) => {
+                let digest = compute_sha256(&entry.path())?;
+                if digest == *recorded { CacheStatus::Valid } else { CacheStatus::Mismatch(digest) }
+            },
+            None => CacheStatus::Unrecorded,
+        };
+        result.push((file_name, status));
+    }
+    for file_name in manifest.keys() {
+        if !directory.join(file_name).exists() {
+            result.push((file_name.clone(), CacheStatus::Missing));
+        }
+    }
+    result.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_SHA256: &str = "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn test_verify_and_evict() {
+        let directory = std::env::temp_dir().join(format!("idf-env-checksum-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let archive = directory.join("archive.zip");
+
+        fs::write(&archive, "hello").unwrap();
+        verify_download(&archive, Some(HELLO_SHA256)).unwrap();
+        assert_eq!(get_recorded_digest(&archive), Some(HELLO_SHA256.to_string()));
+        assert_eq!(verify_directory(&directory).unwrap(), vec![("archive.zip".to_string(), CacheStatus::Valid)]);
+
+        // Cached file was modified, recorded digest does not match anymore
+        fs::write(&archive, "tampered").unwrap();
+        assert!(matches!(verify_directory(&directory).unwrap()[0].1, CacheStatus::Mismatch(_)));
+        assert!(verify_cached(&archive, None).is_err());
+        assert!(!archive.exists());
+        assert_eq!(get_recorded_digest(&archive), None);
+
+        fs::write(&archive, "truncated").unwrap();
+        assert!(verify_download(&archive, Some(HELLO_SHA256)).is_err());
+        assert!(!archive.exists());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_expected_digest() {
+        let digests = [("https://dl.espressif.com/dl/archive.zip", HELLO_SHA256)];
+        assert_eq!(find_digest(&digests, "https://dl.espressif.com/dl/archive.zip"), Some(HELLO_SHA256));
+        assert_eq!(find_digest(&digests, "https://mirror.example.com/dl/archive.zip"), None);
+        assert_eq!(get_expected_digest("https://dl.espressif.com/dl/unknown.zip", Some("abc")), Some("abc".to_string()));
+        assert!(SHIPPED_DIGESTS.iter().all(|(url, digest)| url.starts_with("https://") && !url.contains("/latest/")
+            && digest.len() == 64 && digest.chars().all(|c| c.is_ascii_hexdigit())));
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let directory = std::env::temp_dir().join(format!("idf-env-checksum-concurrent-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let threads: Vec<_> = (0..8).map(|index| {
+            let archive = directory.join(format!("archive-{}.zip", index));
+            std::thread::spawn(move || {
+                fs::write(&archive, "hello").unwrap();
+                verify_download(&archive, None).unwrap();
+            })
+        }).collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(read_manifest(&directory).len(), 8);
+        assert!(verify_directory(&directory).unwrap().iter().all(|(_, status)| *status == CacheStatus::Valid));
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::fmt;
+use std::io;
+
+/// Process exit codes of failed installation, documented in README.
+pub const EXIT_NETWORK: i32 = 2;
+pub const EXIT_CHECKSUM: i32 = 3;
+pub const EXIT_EXTRACTION: i32 = 4;
+pub const EXIT_DISK: i32 = 5;
+pub const EXIT_UNAVAILABLE: i32 = 6;
+pub const EXIT_SIGNATURE: i32 = 7;
+/// Dry run is not a failure, the operation only stops before the first download.
+pub const EXIT_DRY_RUN: i32 = 0;
+
+/// Failure of package download or installation. Each class maps to its own process exit code,
+/// so that installers can tell a network outage from a broken archive.
+#[derive(Debug)]
+pub enum PackageError {
+    /// Connection failed, transfer was interrupted or server returned error status.
+    Network { message: String, transient: bool },
+    /// Downloaded or cached file does not match expected digest.
+    Checksum(String),
+    /// Archive could not be unpacked.
+    Extraction(String),
+    /// Local file system operation failed, e.g. disk full or permission denied.
+    Disk(String),
+    /// Artifact is neither cached nor in the bundle and offline mode forbids the download.
+    Unavailable(String),
+    /// Detached signature is missing, malformed or not made by a trusted key.
+    Signature(String),
+    /// Dry run listed artifacts of the operation, nothing was downloaded or installed.
+    DryRun,
+}
+
+impl PackageError {
+    pub fn network(message: String) -> PackageError {
+        PackageError::Network { message, transient: true }
+    }
+
+    /// Error status returned by server, only 408, 429 and 5xx are worth retrying.
+    pub fn http_status(url: &str, status: u16) -> PackageError {
+        PackageError::Network {
+            message: format!("Download of {} failed: HTTP {}", url, status),
+            transient: status == 408 || status == 429 || status >= 500,
+        }
+    }
+
+    pub fn is_transient(&self) -> bool {
+        matches!(self, PackageError::Network { transient: true, .. })
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            PackageError::Network { .. } => EXIT_NETWORK,
+            PackageError::Checksum(_) => EXIT_CHECKSUM,
+            PackageError::Extraction(_) => EXIT_EXTRACTION,
+            PackageError::Disk(_) => EXIT_DISK,
+            PackageError::Unavailable(_) => EXIT_UNAVAILABLE,
+            PackageError::Signature(_) => EXIT_SIGNATURE,
+            PackageError::DryRun => EXIT_DRY_RUN,
+        }
+    }
+
+    /// Print the error after prefix and exit the process with its exit code. Dry run exits silently.
+    pub fn exit(&self, prefix: &str) -> ! {
+        if !matches!(self, PackageError::DryRun) {
+            println!("{}{}", prefix, self);
+        }
+        std::process::exit(self.exit_code())
+    }
+}
+
+impl fmt::Display for PackageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PackageError::Network { message, .. } => write!(f, "{}", message),
+            PackageError::Checksum(message) => write!(f, "{}", message),
+            PackageError::Extraction(message) => write!(f, "{}", message),
+            PackageError::Disk(message) => write!(f, "{}", message),
+            PackageError::Unavailable(message) => write!(f, "{}", message),
+            PackageError::Signature(message) => write!(f, "{}", message),
+            PackageError::DryRun => write!(f, "Dry run, nothing was installed"),
+        }
+    }
+}
+
+impl std::error::Error for PackageError {}
+
+impl From<io::Error> for PackageError {
+    fn from(e: io::Error) -> PackageError {
+        PackageError::Disk(e.to_string())
+    }
+}
+
+impl From<reqwest::Error> for PackageError {
+    fn from(e: reqwest::Error) -> PackageError {
+        match e.status() {
+            Some(status) => PackageError::http_status(e.url().map(|url| url.as_str()).unwrap_or(""), status.as_u16()),
+            None => PackageError::network(e.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transient_errors() {
+        assert!(PackageError::network("connection reset".to_string()).is_transient());
+        assert!(PackageError::http_status("https://example.com", 503).is_transient());
+        assert!(!PackageError::http_status("https://example.com", 404).is_transient());
+        assert!(!PackageError::Checksum("mismatch".to_string()).is_transient());
+        assert_eq!(PackageError::Disk("disk full".to_string()).exit_code(), EXIT_DISK);
+    }
+}
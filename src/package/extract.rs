@@ -0,0 +1,632 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Component, Path, PathBuf};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use sevenz_rust::{Password, SevenZReader};
+use tar::{Archive, EntryType};
+use xz2::read::XzDecoder;
+
+use super::{PackageError, PackageResult};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Archive formats recognized by their leading bytes, the file extension is not used.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArchiveFormat {
+    Zip,
+    TarGz,
+    TarXz,
+    TarBz2,
+    TarZst,
+    SevenZ,
+}
+
+/// Magic bytes of supported formats. Compressed streams are expected to contain tarball, 7z and zip are archives on their own.
+const SIGNATURES: &[(&[u8], ArchiveFormat)] = &[
+    (b"PK\x03\x04", ArchiveFormat::Zip),
+    // Empty zip archive consists of end of central directory only
+    (b"PK\x05\x06", ArchiveFormat::Zip),
+    (b"\x1f\x8b", ArchiveFormat::TarGz),
+    (b"\xfd7zXZ\x00", ArchiveFormat::TarXz),
+    (b"BZh", ArchiveFormat::TarBz2),
+    (b"\x28\xb5\x2f\xfd", ArchiveFormat::TarZst),
+    (b"7z\xbc\xaf\x27\x1c", ArchiveFormat::SevenZ),
+];
+
+pub fn detect_format(header: &[u8]) -> Option<ArchiveFormat> {
+    SIGNATURES.iter()
+        .find(|(signature, _)| header.starts_with(signature))
+        .map(|(_, format)| *format)
+}
+
+fn read_format(archive_path: &Path) -> Result<ArchiveFormat> {
+    let mut header = Vec::new();
+    File::open(archive_path)?.take(8).read_to_end(&mut header)?;
+    detect_format(&header).ok_or_else(|| "unknown archive format".into())
+}
+
+/// Relative path of archive entry. Absolute paths, drive prefixes and `..` would escape the output
+/// directory, archive containing them is rejected. Backslash is taken as separator, archives created
+/// on Windows use it.
+fn parse_entry_path(name: &str) -> Result<PathBuf> {
+    let name = name.replace('\\', "/");
+    let mut path = PathBuf::new();
+    for component in Path::new(&name).components() {
+        match component {
+            Component::Normal(part) => path.push(part),
+            Component::CurDir => {},
+            _ => return Err(format!("entry `{}` points outside of the output directory", name).into()),
+        }
+    }
+    Ok(path)
+}
+
+/// Target of symbolic link is resolved against the directory of the link, it must stay inside the output.
+/// `..` is accepted only after directories already extracted into the output directory, `..` after
+/// another link or after a path extracted later would be resolved from the target of that link.
+fn check_link_target(directory: &Path, link: &Path, target: &str) -> Result<()> {
+    let mut resolved: PathBuf = link.parent().unwrap_or(Path::new("")).to_path_buf();
+    for component in Path::new(target).components() {
+        match component {
+            Component::Normal(part) => resolved.push(part),
+            Component::CurDir => {},
+            Component::ParentDir if resolved.as_os_str().is_empty() => {
+                return Err(format!("link `{}` -> `{}` points outside of the output directory", link.display(), target).into());
+            },
+            Component::ParentDir => {
+                if !is_directory(&directory.join(&resolved)) {
+                    return Err(format!("link `{}` -> `{}` goes through `{}` which is not an extracted directory",
+                                       link.display(), target, resolved.display()).into());
+                }
+                resolved.pop();
+            },
+            _ => return Err(format!("link `{}` -> `{}` points outside of the output directory", link.display(), target).into()),
+        }
+    }
+    Ok(())
+}
+
+const MODE_TYPE_MASK: u32 = 0o170000;
+const MODE_SYMLINK: u32 = 0o120000;
+
+fn is_symlink_mode(mode: Option<u32>) -> bool {
+    mode.is_some_and(|mode| mode & MODE_TYPE_MASK == MODE_SYMLINK)
+}
+
+/// Permission bits are restored on Unix, setuid, setgid and sticky bits are dropped.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn create_symlink(target: &str, destination: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(target, destination)
+}
+
+/// Symbolic links require a privilege on Windows, the target file is copied when the link can't be created.
+#[cfg(windows)]
+fn create_symlink(target: &str, destination: &Path) -> io::Result<()> {
+    let source = destination.parent().unwrap_or(destination).join(target);
+    match std::os::windows::fs::symlink_file(target, destination) {
+        Ok(_) => Ok(()),
+        Err(_e) if source.is_file() => fs::copy(&source, destination).map(|_| ()),
+        Err(e) => Err(e),
+    }
+}
+
+/// Destination of archive entries inside the output directory. Leading `strip_components` directories
+/// are removed from entry paths. With strip prefix only entries under the prefix are extracted, without the prefix.
+struct Output<'a> {
+    directory: &'a Path,
+    strip_components: usize,
+    strip_prefix: Option<&'a str>,
+}
+
+impl<'a> Output<'a> {
+    /// Path relative to the output directory, None for skipped entries.
+    fn get_relative_path(&self, name: &str) -> Result<Option<PathBuf>> {
+        let entry_path: PathBuf = parse_entry_path(name)?.components().skip(self.strip_components).collect();
+        let relative_path = match self.strip_prefix {
+            Some(prefix) => match entry_path.strip_prefix(prefix) {
+                Ok(relative_path) => relative_path.to_path_buf(),
+                Err(_e) => return Ok(None),
+            },
+            None => entry_path,
+        };
+        Ok(Some(relative_path).filter(|path| !path.as_os_str().is_empty()))
+    }
+
+    /// Create parent directories of the entry. Nothing is written through a symbolic link extracted
+    /// earlier, and existing link at the destination is replaced instead of followed.
+    fn prepare(&self, relative_path: &Path) -> Result<PathBuf> {
+        let mut path = self.directory.to_path_buf();
+        for component in relative_path.components() {
+            if path != self.directory && is_symlink(&path) {
+                return Err(format!("entry `{}` would be written through symbolic link {}", relative_path.display(), path.display()).into());
+            }
+            path.push(component);
+        }
+        create_parent(&path)?;
+        if is_symlink(&path) {
+            fs::remove_file(&path)?;
+        }
+        Ok(path)
+    }
+
+    fn create_directory(&self, relative_path: &Path) -> Result<()> {
+        let destination = self.prepare(relative_path)?;
+        fs::create_dir_all(&destination)?;
+        Ok(())
+    }
+
+    fn write_file(&self, relative_path: &Path, reader: &mut dyn Read, mode: Option<u32>) -> Result<()> {
+        let destination = self.prepare(relative_path)?;
+        let size = io::copy(reader, &mut File::create(&destination)?)?;
+        if let Some(mode) = mode {
+            set_mode(&destination, mode)?;
+        }
+        println!("* extracted: \"{}\" ({} bytes)", destination.display(), size);
+        Ok(())
+    }
+
+    fn write_symlink(&self, relative_path: &Path, target: &str) -> Result<()> {
+        // Parent directories of the link are created first, `..` of the target may go through them
+        let destination = self.prepare(relative_path)?;
+        check_link_target(self.directory, relative_path, target)?;
+        if destination.exists() {
+            fs::remove_file(&destination)?;
+        }
+        create_symlink(target, &destination)?;
+        println!("* linked: \"{}\" -> \"{}\"", destination.display(), target);
+        Ok(())
+    }
+
+    /// Hard link points to a file extracted earlier from the same archive, the file is copied when
+    /// the file system does not support hard links.
+    fn write_hardlink(&self, relative_path: &Path, source_name: &str) -> Result<()> {
+        let source = match self.get_relative_path(source_name)? {
+            Some(source) => self.directory.join(source),
+            None => return Err(format!("hard link `{}` points outside of extracted files", source_name).into()),
+        };
+        if !fs::symlink_metadata(&source).map(|metadata| metadata.is_file()).unwrap_or(false) {
+            return Err(format!("hard link target `{}` is not an extracted file", source_name).into());
+        }
+        let destination = self.prepare(relative_path)?;
+        if destination.exists() {
+            fs::remove_file(&destination)?;
+        }
+        if fs::hard_link(&source, &destination).is_err() {
+            fs::copy(&source, &destination)?;
+        }
+        println!("* linked: \"{}\" => \"{}\"", destination.display(), source.display());
+        Ok(())
+    }
+}
+
+fn is_symlink(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|metadata| metadata.file_type().is_symlink()).unwrap_or(false)
+}
+
+/// Directory which is not a symbolic link.
+fn is_directory(path: &Path) -> bool {
+    fs::symlink_metadata(path).map(|metadata| metadata.is_dir()).unwrap_or(false)
+}
+
+fn create_parent(path: &Path) -> io::Result<()> {
+    match path.parent() {
+        Some(parent) => fs::create_dir_all(parent),
+        None => Ok(()),
+    }
+}
+
+fn read_link_target(reader: &mut dyn Read) -> Result<String> {
+    let mut target = String::new();
+    reader.read_to_string(&mut target)?;
+    Ok(target)
+}
+
+fn extract_zip(archive_path: &Path, output: &Output) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(archive_path)?)?;
+    for index in 0..archive.len() {
+        let mut file = archive.by_index(index)?;
+        let relative_path = match output.get_relative_path(file.name())? {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
+        let mode = file.unix_mode();
+        if file.is_dir() {
+            output.create_directory(&relative_path)?;
+        } else if is_symlink_mode(mode) {
+            // Content of symbolic link entry is its target
+            let target = read_link_target(&mut file)?;
+            output.write_symlink(&relative_path, &target)?;
+        } else {
+            output.write_file(&relative_path, &mut file, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// Attributes of 7z entries created on Unix carry the mode in the upper 16 bits.
+const SEVENZ_UNIX_EXTENSION: u32 = 0x8000;
+
+/// 7z archives with BCJ and BCJ2 filters, e.g. MinGW releases, are decoded natively.
+fn extract_7z(archive_path: &Path, output: &Output) -> Result<()> {
+    let mut archive = SevenZReader::open(archive_path, Password::empty())?;
+    let mut result = Ok(());
+    archive.for_each_entries(|entry, reader| {
+        let relative_path = match output.get_relative_path(entry.name()) {
+            Ok(Some(relative_path)) if !entry.is_anti_item() => relative_path,
+            Ok(_) => {
+                io::copy(reader, &mut io::sink())?;
+                return Ok(true);
+            },
+            Err(e) => {
+                result = Err(e);
+                return Ok(false);
+            },
+        };
+        let attributes = entry.windows_attributes();
+        let mode = Some(attributes >> 16).filter(|_| entry.has_windows_attributes && attributes & SEVENZ_UNIX_EXTENSION != 0);
+        result = if entry.is_directory() {
+            output.create_directory(&relative_path)
+        } else if is_symlink_mode(mode) {
+            read_link_target(reader).and_then(|target| output.write_symlink(&relative_path, &target))
+        } else {
+            output.write_file(&relative_path, reader, mode)
+        };
+        Ok(result.is_ok())
+    })?;
+    result
+}
+
+fn get_tar_reader(format: ArchiveFormat, file: File) -> Result<Box<dyn Read>> {
+    Ok(match format {
+        ArchiveFormat::TarGz => Box::new(GzDecoder::new(file)),
+        ArchiveFormat::TarXz => Box::new(XzDecoder::new(file)),
+        ArchiveFormat::TarBz2 => Box::new(BzDecoder::new(file)),
+        ArchiveFormat::TarZst => Box::new(zstd::stream::read::Decoder::new(file)?),
+        ArchiveFormat::Zip | ArchiveFormat::SevenZ => return Err(format!("{:?} is not a tarball", format).into()),
+    })
+}
+
+fn get_link_name(entry: &tar::Entry<Box<dyn Read>>) -> Result<String> {
+    match entry.link_name_bytes() {
+        Some(link_name) => Ok(String::from_utf8_lossy(&link_name).to_string()),
+        None => Err(format!("link `{}` has no target", String::from_utf8_lossy(&entry.path_bytes())).into()),
+    }
+}
+
+/// Modes of directories are restored after all entries are extracted, so that a read-only directory
+/// can be filled. Owner keeps full access, otherwise the tool could not be removed by uninstall or prune.
+fn extract_tar(reader: Box<dyn Read>, output: &Output) -> Result<()> {
+    let mut archive = Archive::new(reader);
+    let mut directory_modes = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = String::from_utf8_lossy(&entry.path_bytes()).to_string();
+        let relative_path = match output.get_relative_path(&name)? {
+            Some(relative_path) => relative_path,
+            None => continue,
+        };
+        let entry_type = entry.header().entry_type();
+        match entry_type {
+            EntryType::Directory => {
+                output.create_directory(&relative_path)?;
+                if let Ok(mode) = entry.header().mode() {
+                    directory_modes.push((relative_path, mode));
+                }
+            },
+            EntryType::Symlink => output.write_symlink(&relative_path, &get_link_name(&entry)?)?,
+            EntryType::Link => output.write_hardlink(&relative_path, &get_link_name(&entry)?)?,
+            EntryType::Regular | EntryType::Continuous | EntryType::GNUSparse => {
+                let mode = entry.header().mode().ok();
+                output.write_file(&relative_path, &mut entry, mode)?;
+            },
+            // Devices and FIFOs have no place in tool archives
+            _ => println!("* skipped: \"{}\" ({:?})", name, entry_type),
+        }
+    }
+    // Subdirectories first, so that parent stays accessible until its own mode is set
+    for (relative_path, mode) in directory_modes.iter().rev() {
+        set_mode(&output.directory.join(relative_path), mode | 0o700)?;
+    }
+    Ok(())
+}
+
+fn extract_to_output(archive_path: &Path, output: &Output) -> PackageResult<()> {
+    let result = read_format(archive_path).and_then(|format| match format {
+        ArchiveFormat::Zip => extract_zip(archive_path, output),
+        ArchiveFormat::SevenZ => extract_7z(archive_path, output),
+        _ => extract_tar(get_tar_reader(format, File::open(archive_path)?)?, output),
+    });
+    result.map_err(|e| PackageError::Extraction(format!("Unable to extract {}: {}", archive_path.display(), e)))
+}
+
+/// Extract archive of any supported format into existing output directory. With strip prefix
+/// only the content of that top-level directory is extracted. Archive with entries or links pointing
+/// outside of the output directory is rejected. Does not depend on configuration, so that it can be used
+/// for any archive.
+pub fn extract_archive(archive_path: &Path, output_directory: &Path, strip_prefix: Option<&str>) -> PackageResult<()> {
+    extract_to_output(archive_path, &Output { directory: output_directory, strip_components: 0, strip_prefix })
+}
+
+/// Extract archive without the given number of leading directories, e.g. container directory of ESP-IDF tools.
+pub fn extract_archive_strip_components(archive_path: &Path, output_directory: &Path, strip_components: usize) -> PackageResult<()> {
+    extract_to_output(archive_path, &Output { directory: output_directory, strip_components, strip_prefix: None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn get_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("idf-env-extract-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    fn get_tar() -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (path, content) in [("esp/bin/clang", "clang"), ("esp/lib/libclang.so", "lib"), ("README", "readme")] {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder.append_data(&mut header, path, content.as_bytes()).unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    fn compress(format: ArchiveFormat, tar: &[u8]) -> Vec<u8> {
+        match format {
+            ArchiveFormat::TarGz => {
+                let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            ArchiveFormat::TarXz => {
+                let mut encoder = xz2::write::XzEncoder::new(Vec::new(), 6);
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            ArchiveFormat::TarBz2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::default());
+                encoder.write_all(tar).unwrap();
+                encoder.finish().unwrap()
+            },
+            ArchiveFormat::TarZst => zstd::stream::encode_all(tar, 0).unwrap(),
+            ArchiveFormat::SevenZ => {
+                let mut writer = sevenz_rust::SevenZWriter::new(io::Cursor::new(Vec::new())).unwrap();
+                for (path, content) in [("esp\\bin\\clang", "clang"), ("esp/lib/libclang.so", "lib"), ("README", "readme")] {
+                    let mut entry = sevenz_rust::SevenZArchiveEntry::new();
+                    entry.name = path.to_string();
+                    entry.has_stream = true;
+                    writer.push_archive_entry(entry, Some(content.as_bytes())).unwrap();
+                }
+                writer.finish().unwrap().into_inner()
+            },
+            ArchiveFormat::Zip => {
+                let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+                for (path, content) in [("esp/bin/clang", "clang"), ("esp/lib/libclang.so", "lib"), ("README", "readme")] {
+                    writer.start_file(path, zip::write::FileOptions::default()).unwrap();
+                    writer.write_all(content.as_bytes()).unwrap();
+                }
+                writer.finish().unwrap().into_inner()
+            },
+        }
+    }
+
+    #[test]
+    fn test_extract_all_formats() {
+        let directory = get_directory("formats");
+        let tar = get_tar();
+        for format in [ArchiveFormat::Zip, ArchiveFormat::TarGz, ArchiveFormat::TarXz, ArchiveFormat::TarBz2, ArchiveFormat::TarZst, ArchiveFormat::SevenZ] {
+            // Extension is deliberately misleading, format is taken from content
+            let archive_path = directory.join(format!("{:?}.zip", format));
+            fs::write(&archive_path, compress(format, &tar)).unwrap();
+            assert_eq!(read_format(&archive_path).unwrap(), format);
+
+            let output = directory.join(format!("{:?}-full", format));
+            fs::create_dir_all(&output).unwrap();
+            extract_archive(&archive_path, &output, None).unwrap();
+            assert_eq!(fs::read_to_string(output.join("esp/bin/clang")).unwrap(), "clang");
+            assert_eq!(fs::read_to_string(output.join("README")).unwrap(), "readme");
+
+            let output = directory.join(format!("{:?}-stripped", format));
+            fs::create_dir_all(&output).unwrap();
+            extract_archive(&archive_path, &output, Some("esp")).unwrap();
+            assert_eq!(fs::read_to_string(output.join("lib/libclang.so")).unwrap(), "lib");
+            assert!(!output.join("README").exists() && !output.join("esp").exists());
+
+            let output = directory.join(format!("{:?}-components", format));
+            fs::create_dir_all(&output).unwrap();
+            extract_archive_strip_components(&archive_path, &output, 1).unwrap();
+            assert_eq!(fs::read_to_string(output.join("bin/clang")).unwrap(), "clang");
+            assert!(!output.join("README").exists());
+        }
+
+        let unknown = directory.join("unknown.tar.gz");
+        fs::write(&unknown, "plain text").unwrap();
+        let error = extract_archive(&unknown, &directory, None).unwrap_err();
+        assert!(error.to_string().contains("unknown archive format"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// Tar entry stored without validation of the builder, so that malicious names get into the archive.
+    fn append_raw(builder: &mut tar::Builder<Vec<u8>>, entry_type: EntryType, name: &str, link_name: &str, mode: u32) {
+        let content: &[u8] = if entry_type == EntryType::Regular { b"content" } else { b"" };
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_entry_type(entry_type);
+        header.set_link_name_literal(link_name).unwrap();
+        header.set_size(content.len() as u64);
+        header.set_mode(mode);
+        header.set_cksum();
+        builder.append(&header, content).unwrap();
+    }
+
+    fn extract_raw_tar(directory: &Path, entries: &[(EntryType, &str, &str)]) -> PackageResult<PathBuf> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for (entry_type, name, link_name) in entries {
+            let mode = if *entry_type == EntryType::Directory { 0o750 } else { 0o755 };
+            append_raw(&mut builder, *entry_type, name, link_name, mode);
+        }
+        let archive_path = directory.join("raw.tar.gz");
+        fs::write(&archive_path, compress(ArchiveFormat::TarGz, &builder.into_inner().unwrap())).unwrap();
+        let output = directory.join("out/tool");
+        let _ = fs::remove_dir_all(directory.join("out"));
+        fs::create_dir_all(&output).unwrap();
+        extract_archive(&archive_path, &output, None).map(|_| output)
+    }
+
+    #[test]
+    fn test_reject_malicious_tar() {
+        let directory = get_directory("malicious-tar");
+        let malicious: &[&[(EntryType, &str, &str)]] = &[
+            &[(EntryType::Regular, "../evil", "")],
+            &[(EntryType::Regular, "bin/../../evil", "")],
+            &[(EntryType::Regular, "..\\evil", "")],
+            &[(EntryType::Regular, "/tmp/idf-env-evil", "")],
+            &[(EntryType::Symlink, "link", "../evil")],
+            &[(EntryType::Symlink, "bin/link", "../../evil")],
+            &[(EntryType::Symlink, "link", "/etc")],
+            // Link inside the output must not be used to place files elsewhere
+            &[(EntryType::Symlink, "lib", "bin"), (EntryType::Regular, "lib/evil", "")],
+            // Chain of links, `..` would be resolved from the target of the inner link
+            &[(EntryType::Symlink, "sub/l2", ".."), (EntryType::Symlink, "x", "sub/l2/..")],
+            &[(EntryType::Symlink, "x", "sub/l2/../.."), (EntryType::Symlink, "sub/l2", "..")],
+            &[(EntryType::Link, "hard", "../../evil")],
+            &[(EntryType::Link, "hard", "missing")],
+        ];
+        for entries in malicious {
+            let error = extract_raw_tar(&directory, entries).unwrap_err();
+            assert!(error.to_string().contains("outside") || error.to_string().contains("through") || error.to_string().contains("not an extracted file"),
+                    "{:?}: {}", entries, error);
+            assert!(!directory.join("evil").exists() && !directory.join("out/evil").exists());
+        }
+
+        let output = extract_raw_tar(&directory, &[
+            (EntryType::Directory, "share", ""),
+            (EntryType::Regular, "./bin/tool", ""),
+            (EntryType::Symlink, "bin/tool-link", "tool"),
+            (EntryType::Symlink, "share/tool-link", "../bin/tool"),
+            (EntryType::Link, "bin/tool-hard", "bin/tool"),
+        ]).unwrap();
+        assert_eq!(fs::read_to_string(output.join("share/tool-link")).unwrap(), "content");
+        assert_eq!(fs::read_to_string(output.join("bin/tool-hard")).unwrap(), "content");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::read_link(output.join("bin/tool-link")).unwrap(), Path::new("tool"));
+            assert_eq!(fs::metadata(output.join("bin/tool")).unwrap().permissions().mode() & 0o777, 0o755);
+            assert_eq!(fs::metadata(output.join("share")).unwrap().permissions().mode() & 0o777, 0o750);
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    /// 7z archive of one file in a folder with a single coder reading the packed streams directly. Encoders
+    /// of sevenz_rust support neither BCJ nor BCJ2, so the container is assembled by hand.
+    fn build_7z(coder: &[u8], packed_streams: &[&[u8]], unpacked_size: u8, name: &str) -> Vec<u8> {
+        let mut header = vec![0x01, 0x04, 0x06, 0x00, packed_streams.len() as u8, 0x09];
+        header.extend(packed_streams.iter().map(|stream| stream.len() as u8));
+        header.extend([0x00, 0x07, 0x0B, 0x01, 0x00, 0x01]);
+        header.extend(coder);
+        if packed_streams.len() > 1 {
+            header.extend(0..packed_streams.len() as u8);
+        }
+        header.extend([0x0C, unpacked_size, 0x00, 0x08, 0x00, 0x00, 0x05, 0x01, 0x11]);
+        let name: Vec<u8> = name.encode_utf16().chain([0]).flat_map(|unit| unit.to_le_bytes()).collect();
+        header.push(name.len() as u8 + 1);
+        header.push(0x00);
+        header.extend(name);
+        header.extend([0x00, 0x00]);
+
+        let packed: Vec<u8> = packed_streams.concat();
+        let crc32 = |data: &[u8]| {
+            let mut crc = flate2::Crc::new();
+            crc.update(data);
+            crc.sum()
+        };
+        let mut start_header = Vec::new();
+        start_header.extend((packed.len() as u64).to_le_bytes());
+        start_header.extend((header.len() as u64).to_le_bytes());
+        start_header.extend(crc32(&header).to_le_bytes());
+
+        let mut archive = b"7z\xbc\xaf\x27\x1c\x00\x04".to_vec();
+        archive.extend(crc32(&start_header).to_le_bytes());
+        archive.extend(start_header);
+        archive.extend(packed);
+        archive.extend(header);
+        archive
+    }
+
+    #[test]
+    fn test_extract_7z_filters() {
+        let directory = get_directory("7z-filters");
+        let expected = b"ABC\xe8\x10\x00\x00\x00XYZ".to_vec();
+
+        // BCJ x86 stores the call target as absolute address, relative to the end of the instruction
+        let bcj = build_7z(&[0x04, 0x03, 0x03, 0x01, 0x03], &[b"ABC\xe8\x18\x00\x00\x00XYZ"], 11, "bcj.exe");
+        // BCJ2 moves the call target into a separate big endian stream, the range coder selects the conversion
+        let bcj2 = build_7z(&[0x14, 0x03, 0x03, 0x01, 0x1b, 0x04, 0x01],
+                            &[b"ABC\xe8XYZ", b"\x00\x00\x00\x18", b"", b"\x00\x7f\xff\xfc\x00"], 11, "bcj2.exe");
+        for (name, archive) in [("bcj.exe", bcj), ("bcj2.exe", bcj2)] {
+            let archive_path = directory.join(format!("{}.7z", name));
+            fs::write(&archive_path, archive).unwrap();
+            let output = directory.join(name);
+            fs::create_dir_all(&output).unwrap();
+            extract_archive(&archive_path, &output, None).unwrap();
+            assert_eq!(fs::read(output.join(name)).unwrap(), expected, "{}", name);
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_zip_permissions_and_links() {
+        let directory = get_directory("malicious-zip");
+        let write_zip = |entries: &[(&str, Option<&str>)]| -> PathBuf {
+            let mut writer = zip::ZipWriter::new(io::Cursor::new(Vec::new()));
+            for (name, link_target) in entries {
+                match link_target {
+                    Some(target) => writer.add_symlink(*name, *target, zip::write::FileOptions::default()).unwrap(),
+                    None => {
+                        writer.start_file(*name, zip::write::FileOptions::default().unix_permissions(0o755)).unwrap();
+                        writer.write_all(b"#!/bin/sh").unwrap();
+                    },
+                }
+            }
+            let archive_path = directory.join("archive.zip");
+            fs::write(&archive_path, writer.finish().unwrap().into_inner()).unwrap();
+            archive_path
+        };
+        let output = directory.join("out");
+        fs::create_dir_all(&output).unwrap();
+
+        for entries in [&[("../evil", None)][..], &[("link", Some("../evil"))][..]] {
+            assert!(extract_archive(&write_zip(entries), &output, None).is_err());
+            assert!(!directory.join("evil").exists());
+        }
+
+        extract_archive(&write_zip(&[("cargo-espflash", None), ("espflash", Some("cargo-espflash"))]), &output, None).unwrap();
+        assert_eq!(fs::read_to_string(output.join("espflash")).unwrap(), "#!/bin/sh");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(fs::metadata(output.join("cargo-espflash")).unwrap().permissions().mode() & 0o777, 0o755);
+            assert!(fs::symlink_metadata(output.join("espflash")).unwrap().file_type().is_symlink());
+        }
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
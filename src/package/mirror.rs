@@ -0,0 +1,126 @@
+use json::JsonValue;
+use std::env;
+use std::sync::OnceLock;
+
+use super::settings::get_config;
+
+/// Configuration property with rewrite rules, either object `{"prefix": "replacement"}` or string
+/// `prefix=replacement;...`. Environment layer sets it from IDF_ENV_DOWNLOAD_MIRRORS.
+pub const MIRRORS_PROPERTY: &str = "downloadMirrors";
+
+const MIRRORS_VARIABLE: &str = "IDF_ENV_DOWNLOAD_MIRRORS";
+
+/// Compatibility with ESP-IDF tools: GitHub assets are downloaded from https://<IDF_GITHUB_ASSETS>/ instead of https://github.com/.
+const GITHUB_ASSETS_VARIABLE: &str = "IDF_GITHUB_ASSETS";
+
+static RULES: OnceLock<Vec<MirrorRule>> = OnceLock::new();
+
+/// URL prefix replaced by another one, e.g. https://github.com/ by https://mirror.example.com/github/.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MirrorRule {
+    pub prefix: String,
+    pub replacement: String,
+    /// Variable or configuration property which defined the rule.
+    pub source: String,
+}
+
+pub fn parse_rules(value: &str, source: &str) -> Vec<MirrorRule> {
+    value.split(';')
+        .filter_map(|rule| rule.split_once('='))
+        .map(|(prefix, replacement)| (prefix.trim(), replacement.trim()))
+        .filter(|(prefix, _)| !prefix.is_empty())
+        .map(|(prefix, replacement)| MirrorRule {
+            prefix: prefix.to_string(),
+            replacement: replacement.to_string(),
+            source: source.to_string(),
+        })
+        .collect()
+}
+
+fn parse_config_rules(value: &JsonValue) -> Vec<MirrorRule> {
+    if let Some(text) = value.as_str() {
+        return parse_rules(text, MIRRORS_PROPERTY);
+    }
+    value.entries()
+        .filter(|(prefix, _)| !prefix.is_empty())
+        .filter_map(|(prefix, replacement)| replacement.as_str().map(|replacement| MirrorRule {
+            prefix: prefix.to_string(),
+            replacement: replacement.to_string(),
+            source: MIRRORS_PROPERTY.to_string(),
+        }))
+        .collect()
+}
+
+/// Rules from layered configuration followed by IDF_GITHUB_ASSETS, ordered by precedence.
+/// Broken configuration file does not prevent rewriting by the environment variable.
+fn load_mirror_rules() -> Vec<MirrorRule> {
+    let mut rules = match get_config() {
+        Some(config) => config.extra.get(MIRRORS_PROPERTY).map(parse_config_rules).unwrap_or_default(),
+        None => env::var(MIRRORS_VARIABLE).map(|value| parse_rules(&value, MIRRORS_VARIABLE)).unwrap_or_default(),
+    };
+    if let Ok(assets) = env::var(GITHUB_ASSETS_VARIABLE) {
+        let assets = assets.trim().trim_end_matches('/');
+        if !assets.is_empty() {
+            rules.push(MirrorRule {
+                prefix: "https://github.com/".to_string(),
+                replacement: format!("https://{}/", assets),
+                source: GITHUB_ASSETS_VARIABLE.to_string(),
+            });
+        }
+    }
+    rules
+}
+
+/// Rules are resolved once per run.
+pub fn get_mirror_rules() -> &'static [MirrorRule] {
+    RULES.get_or_init(load_mirror_rules)
+}
+
+/// The longest matching prefix wins, rules with the same prefix are taken in order of precedence.
+pub fn find_rule<'a>(url: &str, rules: &'a [MirrorRule]) -> Option<&'a MirrorRule> {
+    let mut found: Option<&MirrorRule> = None;
+    for rule in rules.iter().filter(|rule| url.starts_with(&rule.prefix)) {
+        if found.is_none_or(|found| rule.prefix.len() > found.prefix.len()) {
+            found = Some(rule);
+        }
+    }
+    found
+}
+
+pub fn rewrite_url(url: &str, rules: &[MirrorRule]) -> String {
+    match find_rule(url, rules) {
+        Some(rule) => format!("{}{}", rule.replacement, &url[rule.prefix.len()..]),
+        None => url.to_string(),
+    }
+}
+
+/// URL from which the artifact is really downloaded.
+pub fn resolve_url(url: &str) -> String {
+    let resolved = rewrite_url(url, get_mirror_rules());
+    if resolved != url {
+        println!("Using mirror {} for {}", resolved, url);
+    }
+    resolved
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rewrite_url() {
+        let mut rules = parse_rules("https://github.com/=https://mirror.example.com/github/; https://dl.espressif.com/dl/=https://dl.espressif.cn/dl/", MIRRORS_VARIABLE);
+        rules.extend(parse_config_rules(&json::object! {
+            "https://github.com/esp-rs/": "https://esp-rs.example.com/",
+            "https://github.com/": "https://ignored.example.com/"
+        }));
+        assert_eq!(rules.len(), 4);
+        assert_eq!(rewrite_url("https://github.com/espressif/llvm-project/releases/download/a.tar.xz", &rules),
+                   "https://mirror.example.com/github/espressif/llvm-project/releases/download/a.tar.xz");
+        assert_eq!(rewrite_url("https://github.com/esp-rs/rust-build/releases/download/v1/rust.zip", &rules),
+                   "https://esp-rs.example.com/rust-build/releases/download/v1/rust.zip");
+        assert_eq!(rewrite_url("https://dl.espressif.com/dl/idf-git/git.zip", &rules), "https://dl.espressif.cn/dl/idf-git/git.zip");
+        assert_eq!(rewrite_url("https://www.ftdichip.com/Drivers/CDM.zip", &rules), "https://www.ftdichip.com/Drivers/CDM.zip");
+        assert!(parse_rules("no-separator;=https://x/", MIRRORS_VARIABLE).is_empty());
+    }
+}
@@ -0,0 +1,190 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use super::settings::{get_command_line, get_config};
+use super::{PackageError, PackageResult};
+
+/// Configuration property which disables network access, environment layer sets it from IDF_ENV_OFFLINE.
+pub const OFFLINE_PROPERTY: &str = "offline";
+
+/// Directory with pre-downloaded archives used in addition to the dist directory, IDF_ENV_OFFLINE_BUNDLE_PATH.
+pub const BUNDLE_PROPERTY: &str = "offlineBundlePath";
+
+/// Offline mode and bundle resolved once per run, --offline and --offline-bundle win over configuration.
+struct OfflineSettings {
+    offline: bool,
+    bundle_path: Option<String>,
+}
+
+static SETTINGS: OnceLock<OfflineSettings> = OnceLock::new();
+
+/// Archive which the requested operation needs, stored at `archive` path after download.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Artifact {
+    pub url: String,
+    pub archive: String,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Availability {
+    Cached,
+    Bundle(PathBuf),
+    Missing,
+}
+
+fn is_enabled(value: &str) -> bool {
+    matches!(value.trim().to_lowercase().as_str(), "true" | "yes" | "on" | "1")
+}
+
+/// Broken configuration file does not prevent reading the settings from the environment variables.
+fn get_settings() -> &'static OfflineSettings {
+    SETTINGS.get_or_init(|| {
+        let command_line = get_command_line();
+        let (offline, bundle_path) = match get_config() {
+            Some(config) => (config.get_property(OFFLINE_PROPERTY), config.get_property(BUNDLE_PROPERTY)),
+            None => (env::var("IDF_ENV_OFFLINE").ok(), env::var("IDF_ENV_OFFLINE_BUNDLE_PATH").ok()),
+        };
+        OfflineSettings {
+            offline: command_line.offline || offline.is_some_and(|value| is_enabled(&value)),
+            bundle_path: command_line.bundle_path.clone().or(bundle_path).filter(|path| !path.is_empty()),
+        }
+    })
+}
+
+pub fn is_offline() -> bool {
+    get_settings().offline
+}
+
+/// Set by --dry-run, operations print artifacts they need and stop before the first download.
+pub fn is_dry_run() -> bool {
+    get_command_line().dry_run
+}
+
+pub fn get_bundle_path() -> Option<String> {
+    get_settings().bundle_path.clone()
+}
+
+/// Archives in the bundle are looked up by file name, the bundle is a flat copy of dist directories.
+fn find_in_bundle(bundle_path: Option<&str>, archive: &str) -> Option<PathBuf> {
+    let file_name = Path::new(archive).file_name()?;
+    let path = Path::new(bundle_path?).join(file_name);
+    if path.is_file() { Some(path) } else { None }
+}
+
+pub fn get_availability(bundle_path: Option<&str>, artifact: &Artifact) -> Availability {
+    if Path::new(&artifact.archive).is_file() {
+        return Availability::Cached;
+    }
+    match find_in_bundle(bundle_path, &artifact.archive) {
+        Some(path) => Availability::Bundle(path),
+        None => Availability::Missing,
+    }
+}
+
+/// Copy archive from the bundle to its place in dist directory. Returns false when the bundle does not contain it.
+pub fn copy_from_bundle(archive: &str) -> PackageResult<bool> {
+    let bundle_path = get_bundle_path();
+    let source = match find_in_bundle(bundle_path.as_deref(), archive) {
+        Some(source) => source,
+        None => return Ok(false),
+    };
+    println!("Using archive from bundle: {}", source.display());
+    if let Some(directory) = Path::new(archive).parent() {
+        fs::create_dir_all(directory)?;
+    }
+    fs::copy(&source, archive)
+        .map_err(|e| PackageError::Disk(format!("Unable to copy {} to {}: {}", source.display(), archive, e)))?;
+    Ok(true)
+}
+
+fn get_file_name(artifact: &Artifact) -> String {
+    Path::new(&artifact.archive).file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn format_missing(missing: &[&Artifact]) -> String {
+    missing.iter()
+        .map(|artifact| format!("  {}  {}", get_file_name(artifact), artifact.url))
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+/// Check artifacts of the requested operation before any of them is downloaded. Dry run prints
+/// the artifacts and returns PackageError::DryRun, so that the operation stops and the command decides
+/// about the exit code. In offline mode the operation fails when any artifact is neither cached nor in the bundle.
+pub fn check_artifacts(artifacts: &[Artifact]) -> PackageResult<()> {
+    check_availability(artifacts, get_bundle_path(), is_dry_run(), is_offline())
+}
+
+fn check_availability(artifacts: &[Artifact], bundle_path: Option<String>, dry_run: bool, offline: bool) -> PackageResult<()> {
+    let availability: Vec<Availability> = artifacts.iter()
+        .map(|artifact| get_availability(bundle_path.as_deref(), artifact))
+        .collect();
+    let missing: Vec<&Artifact> = artifacts.iter().zip(&availability)
+        .filter(|(_, availability)| **availability == Availability::Missing)
+        .map(|(artifact, _)| artifact)
+        .collect();
+
+    if dry_run {
+        println!("Artifacts required by the operation:");
+        for (artifact, availability) in artifacts.iter().zip(&availability) {
+            let status = match availability {
+                Availability::Cached => "[cached] ",
+                Availability::Bundle(_) => "[bundle] ",
+                Availability::Missing => "[missing]",
+            };
+            println!("{} {}  {}", status, get_file_name(artifact), artifact.url);
+        }
+        println!("{} of {} artifact(s) missing.", missing.len(), artifacts.len());
+        return Err(PackageError::DryRun);
+    }
+
+    if offline && !missing.is_empty() {
+        return Err(PackageError::Unavailable(format!("Offline mode: {} artifact(s) not found in dist directory{}:\n{}",
+            missing.len(),
+            bundle_path.map(|path| format!(" or bundle {}", path)).unwrap_or_default(),
+            format_missing(&missing))));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_availability() {
+        let directory = std::env::temp_dir().join(format!("idf-env-offline-{}", std::process::id()));
+        let (dist, bundle) = (directory.join("dist"), directory.join("bundle"));
+        fs::create_dir_all(&dist).unwrap();
+        fs::create_dir_all(&bundle).unwrap();
+        fs::write(dist.join("cached.zip"), "cached").unwrap();
+        fs::write(bundle.join("llvm.tar.xz"), "bundled").unwrap();
+        let bundle_path = bundle.display().to_string();
+        let artifact = |file_name: &str| Artifact {
+            url: format!("https://example.com/{}", file_name),
+            archive: dist.join(file_name).display().to_string(),
+        };
+
+        assert_eq!(get_availability(Some(&bundle_path), &artifact("cached.zip")), Availability::Cached);
+        assert_eq!(get_availability(Some(&bundle_path), &artifact("llvm.tar.xz")), Availability::Bundle(bundle.join("llvm.tar.xz")));
+        assert_eq!(get_availability(None, &artifact("llvm.tar.xz")), Availability::Missing);
+        assert_eq!(get_availability(Some(&bundle_path), &artifact("rust.tar.xz")), Availability::Missing);
+        assert!(is_enabled("True") && is_enabled("1") && !is_enabled("false") && !is_enabled(""));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_check_availability() {
+        let artifacts = [Artifact { url: "https://example.com/missing.zip".to_string(), archive: "/nonexistent/missing.zip".to_string() }];
+        assert!(check_availability(&artifacts, None, false, false).is_ok());
+        let error = check_availability(&artifacts, None, false, true).unwrap_err();
+        assert_eq!(error.exit_code(), super::super::error::EXIT_UNAVAILABLE);
+        // Dry run stops the operation also when nothing is missing, the command exits successfully
+        let error = check_availability(&artifacts, None, true, true).unwrap_err();
+        assert!(matches!(error, PackageError::DryRun));
+        assert_eq!(error.exit_code(), 0);
+        assert!(matches!(check_availability(&[], None, true, false), Err(PackageError::DryRun)));
+    }
+}
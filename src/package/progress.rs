@@ -0,0 +1,336 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{IsTerminal, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How download progress is presented, selected by IDF_ENV_PROGRESS=tty|json|none.
+/// Default is tty when stdout is a terminal, otherwise none.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProgressMode {
+    /// Single line updated in place: bytes, rate and ETA.
+    Tty,
+    /// One JSON object per line on stderr, for installers and IDE plugins which parse the output.
+    Json,
+    None,
+}
+
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+pub fn get_progress_mode() -> ProgressMode {
+    match env::var("IDF_ENV_PROGRESS").unwrap_or_default().as_str() {
+        "tty" => ProgressMode::Tty,
+        "json" => ProgressMode::Json,
+        "none" => ProgressMode::None,
+        _ if std::io::stdout().is_terminal() => ProgressMode::Tty,
+        _ => ProgressMode::None,
+    }
+}
+
+pub fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+pub fn format_duration(duration: Duration) -> String {
+    let seconds = duration.as_secs();
+    if seconds >= 3600 {
+        format!("{}h {:02}m", seconds / 3600, seconds % 3600 / 60)
+    } else if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+fn get_rate(bytes: u64, started: Instant) -> u64 {
+    let elapsed = started.elapsed().as_secs_f64();
+    if elapsed <= 0.0 { 0 } else { (bytes as f64 / elapsed) as u64 }
+}
+
+/// JSON events go to stderr, so that they are not mixed with messages printed on stdout.
+fn emit_json(value: json::JsonValue) {
+    eprintln!("{}", value.dump());
+}
+
+fn get_eta(total: Option<u64>, downloaded: u64, rate: u64) -> Option<Duration> {
+    match total {
+        Some(total) if rate > 0 && total >= downloaded => Some(Duration::from_secs((total - downloaded) / rate)),
+        _ => None,
+    }
+}
+
+/// Size and downloaded bytes of one file in combined progress.
+#[derive(Clone, Copy, Default)]
+struct FileState {
+    total: u64,
+    downloaded: u64,
+}
+
+struct CombinedState {
+    completed: usize,
+    /// Files which already received response by file name. Retry of a download replaces the state
+    /// of its previous attempt instead of adding to it.
+    files: BTreeMap<String, FileState>,
+    /// Bytes downloaded before this run by resumed downloads.
+    resumed: u64,
+    last_report: Option<Instant>,
+}
+
+impl CombinedState {
+    fn get_total(&self) -> u64 {
+        self.files.values().map(|file| file.total).sum()
+    }
+
+    fn get_downloaded(&self) -> u64 {
+        self.files.values().map(|file| file.downloaded).sum()
+    }
+}
+
+/// Progress of several concurrent downloads reported as one line or one JSON event,
+/// individual downloads contribute their bytes instead of reporting on their own.
+pub struct CombinedProgress {
+    files: usize,
+    started: Instant,
+    mode: ProgressMode,
+    state: Mutex<CombinedState>,
+}
+
+impl CombinedProgress {
+    pub fn new(files: usize, mode: ProgressMode) -> Arc<CombinedProgress> {
+        Arc::new(CombinedProgress {
+            files,
+            started: Instant::now(),
+            mode,
+            state: Mutex::new(CombinedState { completed: 0, files: BTreeMap::new(), resumed: 0, last_report: None }),
+        })
+    }
+
+    fn add_download(&self, file_name: &str, total: Option<u64>, offset: u64) {
+        let mut state = self.state.lock().unwrap();
+        let file = FileState { total: total.unwrap_or(offset), downloaded: offset };
+        // Offset of retried download was downloaded by this run
+        if state.files.insert(file_name.to_string(), file).is_none() {
+            state.resumed += offset;
+        }
+    }
+
+    fn advance(&self, file_name: &str, bytes: u64) {
+        let mut state = self.state.lock().unwrap();
+        state.files.entry(file_name.to_string()).or_default().downloaded += bytes;
+        if let Some(last_report) = state.last_report {
+            if last_report.elapsed() < REPORT_INTERVAL {
+                return;
+            }
+        }
+        state.last_report = Some(Instant::now());
+        self.report(&state, "downloads-progress");
+    }
+
+    /// Download finished or cached archive was used.
+    pub fn complete_file(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.completed += 1;
+        self.report(&state, "downloads-progress");
+    }
+
+    pub fn finish(&self) {
+        let state = self.state.lock().unwrap();
+        self.report(&state, "downloads-done");
+        if self.mode == ProgressMode::Tty {
+            println!();
+        }
+    }
+
+    fn report(&self, state: &CombinedState, event: &str) {
+        // Total grows as downloads receive response, so there is no reliable ETA
+        let (total, downloaded) = (state.get_total(), state.get_downloaded());
+        let rate = get_rate(downloaded.saturating_sub(state.resumed), self.started);
+        match self.mode {
+            ProgressMode::Tty => {
+                let line = format!("[{}/{}]  {} / {}  {}/s", state.completed, self.files, format_bytes(downloaded),
+                                   format_bytes(total), format_bytes(rate));
+                print!("\r{:<79}", line);
+                let _ = std::io::stdout().flush();
+            },
+            ProgressMode::Json => {
+                let value = json::object! {
+                    "event": event,
+                    "files": self.files,
+                    "completed": state.completed,
+                    "downloaded": downloaded,
+                    "total": total,
+                    "rate": rate,
+                    "elapsedMs": self.started.elapsed().as_millis() as u64
+                };
+                emit_json(value);
+            },
+            ProgressMode::None => {},
+        }
+    }
+}
+
+/// Progress of one download, reports are throttled to REPORT_INTERVAL.
+pub struct DownloadProgress {
+    url: String,
+    file_name: String,
+    total: Option<u64>,
+    downloaded: u64,
+    /// Bytes downloaded by previous attempt when the download was resumed.
+    offset: u64,
+    started: Instant,
+    last_report: Option<Instant>,
+    mode: ProgressMode,
+    combined: Option<Arc<CombinedProgress>>,
+}
+
+impl DownloadProgress {
+    /// Begin reporting, offset is the number of bytes downloaded by previous attempt when the download is resumed.
+    /// Download which is part of combined progress does not report in terminal on its own.
+    pub fn start(url: &str, file_name: &str, total: Option<u64>, offset: u64, mode: ProgressMode,
+                 combined: Option<Arc<CombinedProgress>>) -> DownloadProgress {
+        let mode = match (&combined, mode) {
+            (Some(_), ProgressMode::Tty) => ProgressMode::None,
+            _ => mode,
+        };
+        if let Some(combined) = &combined {
+            combined.add_download(file_name, total, offset);
+        }
+        let progress = DownloadProgress {
+            url: url.to_string(),
+            file_name: file_name.to_string(),
+            total,
+            downloaded: offset,
+            offset,
+            started: Instant::now(),
+            last_report: None,
+            mode,
+            combined,
+        };
+        if mode == ProgressMode::Json {
+            progress.emit_event("download-start");
+        }
+        progress
+    }
+
+    /// Bytes per second since start of the download, data of previous attempt do not count.
+    fn get_rate(&self) -> u64 {
+        get_rate(self.downloaded - self.offset, self.started)
+    }
+
+    fn get_eta(&self) -> Option<Duration> {
+        get_eta(self.total, self.downloaded, self.get_rate())
+    }
+
+    fn emit_event(&self, event: &str) {
+        let mut value = json::object! {
+            "event": event,
+            "url": self.url.clone(),
+            "file": self.file_name.clone(),
+            "downloaded": self.downloaded,
+            "rate": self.get_rate(),
+            "elapsedMs": self.started.elapsed().as_millis() as u64
+        };
+        if let Some(total) = self.total {
+            value["total"] = total.into();
+        }
+        if self.offset > 0 {
+            value["resumedFrom"] = self.offset.into();
+        }
+        if let Some(eta) = self.get_eta() {
+            value["etaSeconds"] = eta.as_secs().into();
+        }
+        emit_json(value);
+    }
+
+    fn format_line(&self) -> String {
+        let size = match self.total {
+            Some(total) => format!("{} / {}", format_bytes(self.downloaded), format_bytes(total)),
+            None => format_bytes(self.downloaded),
+        };
+        let eta = self.get_eta().map(|eta| format!("  ETA {}", format_duration(eta))).unwrap_or_default();
+        format!("{}  {}  {}/s{}", self.file_name, size, format_bytes(self.get_rate()), eta)
+    }
+
+    pub fn advance(&mut self, bytes: u64) {
+        self.downloaded += bytes;
+        if let Some(combined) = &self.combined {
+            combined.advance(&self.file_name, bytes);
+        }
+        if self.mode == ProgressMode::None {
+            return;
+        }
+        if let Some(last_report) = self.last_report {
+            if last_report.elapsed() < REPORT_INTERVAL {
+                return;
+            }
+        }
+        self.last_report = Some(Instant::now());
+        match self.mode {
+            ProgressMode::Tty => {
+                // Pad the line to overwrite longer previous output
+                print!("\r{:<79}", self.format_line());
+                let _ = std::io::stdout().flush();
+            },
+            ProgressMode::Json => self.emit_event("download-progress"),
+            ProgressMode::None => {},
+        }
+    }
+
+    pub fn finish(&self) {
+        match self.mode {
+            ProgressMode::Tty => println!("\r{:<79}", self.format_line()),
+            ProgressMode::Json => self.emit_event("download-done"),
+            ProgressMode::None => {},
+        }
+    }
+
+    pub fn fail(&self, error: &str) {
+        match self.mode {
+            ProgressMode::Tty => println!(),
+            ProgressMode::Json => {
+                emit_json(json::object! { "event": "download-failed", "url": self.url.clone(), "file": self.file_name.clone(), "error": error });
+            },
+            ProgressMode::None => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        assert_eq!(format_bytes(512), "512 B");
+        assert_eq!(format_bytes(1536), "1.5 KiB");
+        assert_eq!(format_bytes(500 * 1024 * 1024), "500.0 MiB");
+        assert_eq!(format_duration(Duration::from_secs(42)), "42s");
+        assert_eq!(format_duration(Duration::from_secs(112)), "1m 52s");
+        assert_eq!(format_duration(Duration::from_secs(3720)), "1h 02m");
+    }
+
+    #[test]
+    fn test_combined_progress_retry() {
+        let combined = CombinedProgress::new(2, ProgressMode::None);
+        combined.add_download("llvm.tar.xz", Some(1000), 0);
+        combined.add_download("rust.tar.xz", Some(500), 100);
+        combined.advance("llvm.tar.xz", 300);
+        // Retry resumes llvm from byte 300
+        combined.add_download("llvm.tar.xz", Some(1000), 300);
+        combined.advance("llvm.tar.xz", 200);
+        let state = combined.state.lock().unwrap();
+        assert_eq!(state.get_total(), 1500);
+        assert_eq!(state.get_downloaded(), 600);
+        assert_eq!(state.resumed, 100);
+    }
+}
@@ -0,0 +1,102 @@
+use reqwest::header::{HeaderMap, CONTENT_RANGE, ETAG, LAST_MODIFIED};
+use std::fs;
+use std::path::Path;
+
+/// Download interrupted earlier which can continue from `offset` when the remote file still matches `validator`.
+pub struct PartialDownload {
+    pub offset: u64,
+    /// Value for If-Range header: strong ETag or Last-Modified of the original response.
+    pub validator: String,
+}
+
+pub fn get_part_path(output: &str) -> String {
+    format!("{}.part", output)
+}
+
+/// Validator of .part file is stored next to it, so that another run can resume the download.
+fn get_meta_path(output: &str) -> String {
+    format!("{}.part.meta", output)
+}
+
+/// Partial download of the same URL which can be resumed, if any.
+pub fn find_resumable(output: &str, url: &str) -> Option<PartialDownload> {
+    let offset = fs::metadata(get_part_path(output)).ok()?.len();
+    if offset == 0 {
+        return None;
+    }
+    let meta = json::parse(&fs::read_to_string(get_meta_path(output)).ok()?).ok()?;
+    if meta["url"].as_str() != Some(url) {
+        return None;
+    }
+    let validator = meta["validator"].as_str()?.to_string();
+    Some(PartialDownload { offset, validator })
+}
+
+/// Remember validator of response which starts a new download. Weak ETags can't be used with If-Range,
+/// Last-Modified is used instead. Without validator the download can't be resumed safely.
+pub fn save_validator(output: &str, url: &str, headers: &HeaderMap) {
+    let etag = headers.get(ETAG)
+        .and_then(|value| value.to_str().ok())
+        .filter(|etag| !etag.starts_with("W/"));
+    let validator = etag.or_else(|| headers.get(LAST_MODIFIED).and_then(|value| value.to_str().ok()));
+    match validator {
+        Some(validator) => {
+            let meta = json::object! { "url": url, "validator": validator };
+            let _ = fs::write(get_meta_path(output), meta.dump());
+        },
+        None => { let _ = fs::remove_file(get_meta_path(output)); },
+    }
+}
+
+/// Remove partial download and its validator.
+pub fn discard(output: &str) {
+    for path in &[get_part_path(output), get_meta_path(output)] {
+        if Path::new(path).exists() {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// First byte of partial response, e.g. 100 for `bytes 100-199/200`.
+pub fn get_content_range_start(headers: &HeaderMap) -> Option<u64> {
+    let value = headers.get(CONTENT_RANGE)?.to_str().ok()?;
+    let range = value.trim().strip_prefix("bytes ")?;
+    range.split('-').next()?.trim().parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    #[test]
+    fn test_resumable_download() {
+        let directory = std::env::temp_dir().join(format!("idf-env-resume-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let output = directory.join("llvm.tar.xz").display().to_string();
+        let url = "https://example.com/llvm.tar.xz";
+
+        let mut headers = HeaderMap::new();
+        headers.insert(ETAG, HeaderValue::from_static("W/\"weak\""));
+        headers.insert(LAST_MODIFIED, HeaderValue::from_static("Wed, 21 Oct 2015 07:28:00 GMT"));
+        save_validator(&output, url, &headers);
+        assert!(find_resumable(&output, url).is_none());
+
+        fs::write(get_part_path(&output), "partial").unwrap();
+        let partial = find_resumable(&output, url).unwrap();
+        assert_eq!(partial.offset, 7);
+        assert_eq!(partial.validator, "Wed, 21 Oct 2015 07:28:00 GMT");
+        assert!(find_resumable(&output, "https://mirror.example.com/llvm.tar.xz").is_none());
+
+        discard(&output);
+        assert!(find_resumable(&output, url).is_none());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_get_content_range_start() {
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_RANGE, HeaderValue::from_static("bytes 100-199/200"));
+        assert_eq!(get_content_range_start(&headers), Some(100));
+    }
+}
@@ -0,0 +1,50 @@
+use std::env;
+use std::time::Duration;
+
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BASE_DELAY_MS: u64 = 1000;
+const MAX_DELAY: Duration = Duration::from_secs(60);
+
+/// Retries of failed downloads with exponential backoff. Configured by IDF_ENV_DOWNLOAD_RETRIES
+/// (number of retries after the first attempt) and IDF_ENV_DOWNLOAD_RETRY_DELAY_MS (delay before the first retry).
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    pub retries: u32,
+    pub base_delay: Duration,
+}
+
+fn get_env_number(variable_name: &str, default: u64) -> u64 {
+    env::var(variable_name).ok()
+        .and_then(|value| value.trim().parse().ok())
+        .unwrap_or(default)
+}
+
+impl RetryPolicy {
+    pub fn from_env() -> RetryPolicy {
+        RetryPolicy {
+            retries: get_env_number("IDF_ENV_DOWNLOAD_RETRIES", DEFAULT_RETRIES as u64) as u32,
+            base_delay: Duration::from_millis(get_env_number("IDF_ENV_DOWNLOAD_RETRY_DELAY_MS", DEFAULT_BASE_DELAY_MS)),
+        }
+    }
+
+    /// Delay before retry number `attempt` counted from zero: base, 2 * base, 4 * base, ... up to one minute.
+    pub fn get_delay(&self, attempt: u32) -> Duration {
+        let factor = 2u32.saturating_pow(attempt);
+        self.base_delay.checked_mul(factor).unwrap_or(MAX_DELAY).min(MAX_DELAY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_exponential_backoff() {
+        let policy = RetryPolicy { retries: 5, base_delay: Duration::from_millis(500) };
+        assert_eq!(policy.get_delay(0), Duration::from_millis(500));
+        assert_eq!(policy.get_delay(1), Duration::from_secs(1));
+        assert_eq!(policy.get_delay(3), Duration::from_secs(4));
+        assert_eq!(policy.get_delay(20), MAX_DELAY);
+        assert_eq!(policy.get_delay(40), MAX_DELAY);
+    }
+}
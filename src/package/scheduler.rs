@@ -0,0 +1,200 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+
+use tokio::runtime::Handle;
+use tokio::sync::Semaphore;
+
+use super::offline::{check_artifacts, Artifact};
+use super::progress::{get_progress_mode, CombinedProgress};
+use super::retry::RetryPolicy;
+use super::{download_zip, PackageError, PackageResult};
+
+const DEFAULT_PARALLELISM: usize = 4;
+
+/// Number of concurrent downloads: explicit value, IDF_ENV_DOWNLOAD_PARALLELISM or default.
+pub fn get_parallelism(value: Option<&str>) -> usize {
+    value.map(|value| value.to_string())
+        .or_else(|| env::var("IDF_ENV_DOWNLOAD_PARALLELISM").ok())
+        .and_then(|value| value.trim().parse().ok())
+        .filter(|parallelism| *parallelism > 0)
+        .unwrap_or(DEFAULT_PARALLELISM)
+}
+
+type Install<'a> = Box<dyn FnOnce() -> PackageResult<()> + 'a>;
+
+struct ScheduledPackage<'a> {
+    name: String,
+    url: String,
+    archive: String,
+    sha256: Option<String>,
+    /// Indices of packages which must be installed first.
+    dependencies: Vec<usize>,
+    install: Option<Install<'a>>,
+}
+
+/// Downloads archives of several packages concurrently and installs each package as soon as
+/// its archive is downloaded and all packages it depends on are installed.
+pub struct Scheduler<'a> {
+    parallelism: usize,
+    packages: Vec<ScheduledPackage<'a>>,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn new(parallelism: usize) -> Scheduler<'a> {
+        Scheduler { parallelism: parallelism.max(1), packages: Vec::new() }
+    }
+
+    /// Add package downloaded from url to archive path. Dependencies are names of packages added earlier,
+    /// so that there is no cycle. Install step usually extracts the cached archive by prepare_package*.
+    pub fn add<F>(&mut self, name: &str, url: &str, archive: &str, sha256: Option<&str>, dependencies: &[&str], install: F)
+        where F: FnOnce() -> PackageResult<()> + 'a
+    {
+        let dependencies = dependencies.iter()
+            .map(|dependency| self.packages.iter().position(|package| package.name == *dependency)
+                .unwrap_or_else(|| panic!("Package {} depends on {} which was not added before", name, dependency)))
+            .collect();
+        self.packages.push(ScheduledPackage {
+            name: name.to_string(),
+            url: url.to_string(),
+            archive: archive.to_string(),
+            sha256: sha256.map(|digest| digest.to_string()),
+            dependencies,
+            install: Some(Box::new(install)),
+        });
+    }
+
+    /// Download all archives, at most `parallelism` at once, and install packages in dependency order.
+    /// Failed download stops further installations, but running downloads complete, so that the next run
+    /// finds them in the cache. The first error is returned. Offline mode and dry run check all archives first,
+    /// dry run returns PackageError::DryRun also when there is nothing to download, so that no step after the run is executed.
+    pub fn run(mut self) -> PackageResult<()> {
+        let artifacts: Vec<Artifact> = self.packages.iter()
+            .map(|package| Artifact { url: package.url.clone(), archive: package.archive.clone() })
+            .collect();
+        check_artifacts(&artifacts)?;
+        if self.packages.is_empty() {
+            return Ok(());
+        }
+        for package in &self.packages {
+            if let Some(directory) = Path::new(&package.archive).parent() {
+                fs::create_dir_all(directory)
+                    .map_err(|e| PackageError::Disk(format!("Unable to create {}: {}", directory.display(), e)))?;
+            }
+        }
+
+        let handle = Handle::current();
+        let semaphore = Arc::new(Semaphore::new(self.parallelism));
+        let combined = CombinedProgress::new(self.packages.len(), get_progress_mode());
+        let policy = RetryPolicy::from_env();
+        let (sender, receiver) = mpsc::channel();
+        println!("Downloading {} package(s), {} at once", self.packages.len(), self.parallelism);
+        let tasks: Vec<_> = self.packages.iter().enumerate().map(|(index, package)| {
+            let (url, archive, sha256) = (package.url.clone(), package.archive.clone(), package.sha256.clone());
+            let (semaphore, combined, sender) = (semaphore.clone(), combined.clone(), sender.clone());
+            handle.spawn(async move {
+                let _permit = semaphore.acquire_owned().await;
+                let result = download_zip(url, archive, sha256, policy, Some(combined.clone())).await;
+                combined.complete_file();
+                let _ = sender.send((index, result));
+            })
+        }).collect();
+        drop(sender);
+
+        let result = self.install_in_order(receiver, &combined);
+        // Remaining downloads are only stopped when installation failed
+        for task in tasks {
+            task.abort();
+        }
+        result
+    }
+
+    fn get_ready_package(&self, downloaded: &[bool], installed: &[bool]) -> Option<usize> {
+        (0..self.packages.len()).find(|&index| downloaded[index] && !installed[index]
+            && self.packages[index].dependencies.iter().all(|&dependency| installed[dependency]))
+    }
+
+    fn install_in_order(&mut self, receiver: mpsc::Receiver<(usize, PackageResult<()>)>, combined: &CombinedProgress) -> PackageResult<()> {
+        let mut downloaded = vec![false; self.packages.len()];
+        let mut installed = vec![false; self.packages.len()];
+        let mut received = 0;
+        let mut first_error = None;
+        for (index, result) in receiver {
+            received += 1;
+            if received == self.packages.len() {
+                combined.finish();
+            }
+            match result {
+                Ok(_) => downloaded[index] = true,
+                Err(e) => {
+                    println!("Download of {} failed: {}", self.packages[index].name, e);
+                    first_error.get_or_insert(e);
+                },
+            }
+            if first_error.is_some() {
+                continue;
+            }
+            while let Some(ready) = self.get_ready_package(&downloaded, &installed) {
+                println!("Installing {}", self.packages[ready].name);
+                if let Some(install) = self.packages[ready].install.take() {
+                    install()?;
+                }
+                installed[ready] = true;
+            }
+        }
+        match first_error {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{get_body, get_output, respond_full, respond_status, serve};
+    use std::cell::RefCell;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_install_in_dependency_order() {
+        let (directory, output) = get_output("scheduler");
+        let server = serve(vec![respond_full(), respond_full(), respond_full()]);
+        let installed = RefCell::new(Vec::new());
+        let packages = [("rust", vec![]), ("rust-src", vec!["rust"]), ("llvm", vec![])];
+        let mut scheduler = Scheduler::new(3);
+        for (name, dependencies) in &packages {
+            let archive = format!("{}.{}", output, name);
+            let downloaded = archive.clone();
+            let installed = &installed;
+            scheduler.add(name, &format!("{}/{}", server, name), &archive, None, dependencies, move || {
+                assert_eq!(fs::read(&downloaded).unwrap(), get_body());
+                installed.borrow_mut().push(name.to_string());
+                Ok(())
+            });
+        }
+        scheduler.run().unwrap();
+        let installed = installed.into_inner();
+        assert_eq!(installed.len(), 3);
+        let position = |name: &str| installed.iter().position(|installed| installed == name).unwrap();
+        assert!(position("rust") < position("rust-src"));
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_failed_download_stops_installation() {
+        let (directory, output) = get_output("scheduler-failure");
+        let server = serve(vec![respond_status("404 Not Found")]);
+        let mut scheduler = Scheduler::new(2);
+        scheduler.add("missing", &format!("{}/missing", server), &output, None, &[], || panic!("installed without archive"));
+        let error = scheduler.run().unwrap_err();
+        assert!(!error.is_transient());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_get_parallelism() {
+        assert_eq!(get_parallelism(Some("8")), 8);
+        assert_eq!(get_parallelism(Some("0")), get_parallelism(Some("auto")));
+    }
+}
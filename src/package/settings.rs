@@ -0,0 +1,29 @@
+use std::sync::OnceLock;
+
+use crate::config::{peek_config, EspIdfConfig};
+
+/// Global switches of the command line which change how packages are obtained.
+#[derive(Clone, Debug, Default)]
+pub struct CommandLine {
+    pub offline: bool,
+    pub bundle_path: Option<String>,
+    pub dry_run: bool,
+}
+
+static COMMAND_LINE: OnceLock<CommandLine> = OnceLock::new();
+
+static CONFIG: OnceLock<Option<EspIdfConfig>> = OnceLock::new();
+
+/// Store global switches before the command runs, switches of the command line win over configuration.
+pub fn set_command_line(command_line: CommandLine) {
+    let _ = COMMAND_LINE.set(command_line);
+}
+
+pub fn get_command_line() -> &'static CommandLine {
+    COMMAND_LINE.get_or_init(CommandLine::default)
+}
+
+/// Layered configuration read once per run without modifying esp_idf.json, None when it's not readable.
+pub fn get_config() -> Option<&'static EspIdfConfig> {
+    CONFIG.get_or_init(|| peek_config().ok()).as_ref()
+}
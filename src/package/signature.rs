@@ -0,0 +1,182 @@
+use json::JsonValue;
+use minisign_verify::{Error, PublicKey, Signature};
+use std::env;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use super::offline::Artifact;
+use super::settings::get_config;
+use super::{PackageError, PackageResult};
+
+/// Detached minisign signature is published next to the artifact, e.g. driver.zip.minisig.
+pub const SIGNATURE_EXTENSION: &str = ".minisig";
+
+/// Configuration property with trusted public keys, either array of keys or string `key;key`.
+/// Environment layer sets it from IDF_ENV_SIGNING_KEYS.
+pub const SIGNING_KEYS_PROPERTY: &str = "signingKeys";
+
+const SIGNING_KEYS_VARIABLE: &str = "IDF_ENV_SIGNING_KEYS";
+
+/// Public keys of Espressif compiled into idf-env, they are trusted in addition to the configured ones.
+const PINNED_PUBLIC_KEYS: &[&str] = &[];
+
+/// Only Espressif publishes detached signatures, artifacts of other vendors are never verified.
+const SIGNED_HOSTS: &[&str] = &["https://dl.espressif.com/", "https://github.com/espressif/"];
+
+pub fn is_signed_host(url: &str) -> bool {
+    SIGNED_HOSTS.iter().any(|host| url.starts_with(host))
+}
+
+pub fn get_signature_path(archive: &str) -> String {
+    format!("{}{}", archive, SIGNATURE_EXTENSION)
+}
+
+pub fn get_signature_artifact(url: &str, archive: &str) -> Artifact {
+    Artifact { url: format!("{}{}", url, SIGNATURE_EXTENSION), archive: get_signature_path(archive) }
+}
+
+pub fn parse_keys(value: &JsonValue) -> Vec<String> {
+    let keys: Vec<String> = match value.as_str() {
+        Some(text) => text.split(';').map(|key| key.to_string()).collect(),
+        None => value.members().filter_map(|key| key.as_str()).map(|key| key.to_string()).collect(),
+    };
+    keys.iter().map(|key| key.trim().to_string()).filter(|key| !key.is_empty()).collect()
+}
+
+/// Pinned keys followed by keys from layered configuration. Broken configuration file does not prevent
+/// reading keys from the environment variable. Key which can't be decoded is an error, so that a typo
+/// does not silently turn the verification off.
+pub fn get_trusted_keys() -> PackageResult<Vec<PublicKey>> {
+    let configured = match get_config() {
+        Some(config) => config.extra.get(SIGNING_KEYS_PROPERTY).map(parse_keys).unwrap_or_default(),
+        None => env::var(SIGNING_KEYS_VARIABLE).map(|value| parse_keys(&value.into())).unwrap_or_default(),
+    };
+    PINNED_PUBLIC_KEYS.iter().map(|key| key.to_string()).chain(configured)
+        .map(|key| PublicKey::from_base64(&key)
+            .map_err(|e| PackageError::Signature(format!("Invalid signing key {}: {}", key, e))))
+        .collect()
+}
+
+/// Keys which verify the artifact downloaded from URL. Verification is opt-in: artifact of other vendor
+/// or without any trusted key returns no key and is installed without signature check.
+pub fn get_verification_keys(url: &str) -> PackageResult<Vec<PublicKey>> {
+    if !is_signed_host(url) {
+        return Ok(Vec::new());
+    }
+    get_trusted_keys()
+}
+
+fn get_error(file_path: &Path, e: Error) -> PackageError {
+    PackageError::Signature(format!("Signature verification of {} failed: {}", file_path.display(), e))
+}
+
+/// Verify file by its detached signature made by any of the trusted keys. Only pre-hashed signatures
+/// are accepted, which is the default of minisign since version 0.10.
+pub fn verify_file(file_path: &Path, signature_path: &Path, keys: &[PublicKey]) -> PackageResult<()> {
+    let signature = Signature::from_file(signature_path).map_err(|e| get_error(file_path, e))?;
+    for key in keys {
+        let mut verifier = match key.verify_stream(&signature) {
+            Ok(verifier) => verifier,
+            Err(Error::UnexpectedKeyId) => continue,
+            Err(e) => return Err(get_error(file_path, e)),
+        };
+        let mut file = File::open(file_path)
+            .map_err(|e| PackageError::Disk(format!("Unable to open {}: {}", file_path.display(), e)))?;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let length = file.read(&mut buffer)?;
+            if length == 0 {
+                break;
+            }
+            verifier.update(&buffer[..length]);
+        }
+        return verifier.finalize().map_err(|e| get_error(file_path, e));
+    }
+    Err(PackageError::Signature(format!("Signature of {} was not made by any trusted key ({})",
+                                        file_path.display(), signature.untrusted_comment())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::tests::{get_output, respond_status, serve, Responder};
+    use super::super::verify_signature;
+    use std::fs;
+
+    // Test vector of minisign: signature of content "test"
+    const PUBLIC_KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==
+";
+
+    #[test]
+    fn test_verify_file() {
+        let directory = std::env::temp_dir().join(format!("idf-env-signature-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let file_path = directory.join("driver.zip");
+        let signature_path = directory.join("driver.zip.minisig");
+        fs::write(&file_path, "test").unwrap();
+        fs::write(&signature_path, SIGNATURE).unwrap();
+        let trusted = PublicKey::from_base64(PUBLIC_KEY).unwrap();
+        // Same key material with another key id
+        let other = PublicKey::from_base64("RWQg6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3").unwrap();
+
+        verify_file(&file_path, &signature_path, &[other.clone(), trusted.clone()]).unwrap();
+        let error = verify_file(&file_path, &signature_path, &[other]).unwrap_err();
+        assert!(error.to_string().contains("not made by any trusted key"));
+        assert_eq!(error.exit_code(), super::super::error::EXIT_SIGNATURE);
+
+        fs::write(&file_path, "tampered").unwrap();
+        assert!(verify_file(&file_path, &signature_path, std::slice::from_ref(&trusted)).is_err());
+        fs::write(&signature_path, "not a signature").unwrap();
+        assert!(verify_file(&file_path, &signature_path, &[trusted]).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    fn respond_signature() -> Responder {
+        Box::new(|_request| format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", SIGNATURE.len(), SIGNATURE).into_bytes())
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_verify_downloaded_signature() {
+        let (directory, output) = get_output("signature");
+        let signature_path = get_signature_path(&output);
+        let key = PublicKey::from_base64(PUBLIC_KEY).unwrap();
+        fs::write(&output, "test").unwrap();
+        let url = format!("{}/archive.zip", serve(vec![respond_signature()]));
+        verify_signature(&url, &output, std::slice::from_ref(&key)).unwrap();
+        assert_eq!(fs::read_to_string(&signature_path).unwrap(), SIGNATURE);
+
+        // Cached signature is reused, archive which does not verify is removed together with it
+        fs::write(&output, "tampered").unwrap();
+        let error = verify_signature(&url, &output, &[key]).unwrap_err();
+        assert_eq!(error.exit_code(), super::super::error::EXIT_SIGNATURE);
+        assert!(!Path::new(&output).exists() && !Path::new(&signature_path).exists());
+
+        // Signature which is not published is a signature failure, not a network one
+        fs::write(&output, "test").unwrap();
+        let url = format!("{}/archive.zip", serve(vec![respond_status("404 Not Found")]));
+        let error = verify_signature(&url, &output, &[PublicKey::from_base64(PUBLIC_KEY).unwrap()]).unwrap_err();
+        assert_eq!(error.exit_code(), super::super::error::EXIT_SIGNATURE);
+        fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[test]
+    fn test_verification_keys() {
+        assert!(is_signed_host("https://dl.espressif.com/dl/idf-driver/idf-driver-esp32-usb-jtag-2021-07-15.zip"));
+        assert!(is_signed_host("https://github.com/espressif/llvm-project/releases/download/esp-14.0.0-20220415/llvm.tar.xz"));
+        assert!(!is_signed_host("https://github.com/espressif-fake/releases/driver.zip"));
+        assert!(!is_signed_host("https://www.silabs.com/documents/public/software/CP210x_Universal_Windows_Driver.zip"));
+        // Vendor artifact is not verified even when keys are trusted
+        assert!(get_verification_keys("https://www.wch.cn/downloads/file/314.html").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_parse_keys() {
+        assert_eq!(parse_keys(&" RWQa ; RWQb;".into()), vec!["RWQa".to_string(), "RWQb".to_string()]);
+        assert_eq!(parse_keys(&json::array!["RWQa", 1, ""]), vec!["RWQa".to_string()]);
+    }
+}
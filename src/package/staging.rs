@@ -0,0 +1,166 @@
+use fs2::FileExt;
+use std::fs::{self, File, OpenOptions};
+use std::path::{Path, PathBuf};
+
+use super::{PackageError, PackageResult};
+
+/// File written into output directory after successful extraction. Extraction moves the complete result
+/// to the output at once, so directory without it was not created by this idf-env and is never replaced.
+pub const COMPLETE_MARKER: &str = ".idf-env-complete";
+
+pub fn is_complete(output_directory: &Path) -> bool {
+    output_directory.join(COMPLETE_MARKER).is_file()
+}
+
+/// Complete directory, or non-empty directory without marker, e.g. installed by an earlier version
+/// of idf-env. Such installation is kept as it is, only an empty directory is installed again.
+pub fn is_installed(output_directory: &Path) -> bool {
+    is_complete(output_directory) || (output_directory.is_dir() && !is_empty_directory(output_directory))
+}
+
+/// Mark directory filled by an installer instead of extraction as complete.
+pub fn mark_complete(output_directory: &Path, source: &str) -> PackageResult<()> {
+    fs::write(output_directory.join(COMPLETE_MARKER), source).map_err(|e| get_disk_error("write to", output_directory, e))
+}
+
+/// Staging directories are hidden siblings of the output, so that the final rename stays on one file system.
+fn get_staging_prefix(output_directory: &Path) -> String {
+    format!(".{}.staging-", output_directory.file_name().unwrap_or_default().to_string_lossy())
+}
+
+fn get_staging_path(output_directory: &Path) -> PathBuf {
+    output_directory.with_file_name(format!("{}{}", get_staging_prefix(output_directory), std::process::id()))
+}
+
+/// Lock file held by the process which extracts into the staging directory.
+fn get_lock_path(staging_path: &Path) -> PathBuf {
+    staging_path.with_file_name(format!("{}.lock", staging_path.file_name().unwrap_or_default().to_string_lossy()))
+}
+
+fn open_lock(staging_path: &Path) -> std::io::Result<File> {
+    OpenOptions::new().read(true).write(true).create(true).truncate(false).open(get_lock_path(staging_path))
+}
+
+/// Remove staging directories of the output left by interrupted runs. Staging directory whose lock
+/// is held belongs to a running extraction and is kept.
+pub fn remove_orphans(output_directory: &Path) {
+    let prefix = get_staging_prefix(output_directory);
+    let entries = match output_directory.parent().map(fs::read_dir) {
+        Some(Ok(entries)) => entries,
+        _ => return,
+    };
+    for entry in entries.filter_map(|entry| entry.ok()) {
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        if !file_name.starts_with(&prefix) || file_name.ends_with(".lock") {
+            continue;
+        }
+        let lock = match open_lock(&entry.path()) {
+            Ok(lock) => lock,
+            Err(_) => continue,
+        };
+        if lock.try_lock_exclusive().is_err() {
+            continue;
+        }
+        println!("Removing orphaned staging directory: {}", entry.path().display());
+        if let Err(e) = fs::remove_dir_all(entry.path()) {
+            println!("Unable to remove {}: {}", entry.path().display(), e);
+        }
+        drop(lock);
+        let _ = fs::remove_file(get_lock_path(&entry.path()));
+    }
+}
+
+fn get_disk_error(action: &str, path: &Path, e: std::io::Error) -> PackageError {
+    PackageError::Disk(format!("Unable to {} {}: {}", action, path.display(), e))
+}
+
+fn is_empty_directory(path: &Path) -> bool {
+    fs::read_dir(path).map(|mut entries| entries.next().is_none()).unwrap_or(false)
+}
+
+/// Run `extract` into an empty staging directory and move the result to output directory when it succeeds.
+/// Existing output without completion marker is a previous installation and is kept, only an empty
+/// directory is replaced. Failed extraction leaves neither output nor staging directory.
+pub fn extract_staged<F>(output_directory: &Path, source: &str, extract: F) -> PackageResult<()>
+    where F: FnOnce(&Path) -> PackageResult<()>
+{
+    remove_orphans(output_directory);
+    if output_directory.exists() {
+        if !is_empty_directory(output_directory) {
+            println!("Previous installation exists in {}, skipping", output_directory.display());
+            return Ok(());
+        }
+        fs::remove_dir(output_directory).map_err(|e| get_disk_error("remove", output_directory, e))?;
+    }
+
+    let staging_path = get_staging_path(output_directory);
+    let lock = open_lock(&staging_path).map_err(|e| get_disk_error("create", &get_lock_path(&staging_path), e))?;
+    lock.lock_exclusive().map_err(|e| get_disk_error("lock", &get_lock_path(&staging_path), e))?;
+    let result = extract_into(output_directory, &staging_path, source, extract);
+    drop(lock);
+    let _ = fs::remove_file(get_lock_path(&staging_path));
+    result
+}
+
+fn extract_into<F>(output_directory: &Path, staging_path: &Path, source: &str, extract: F) -> PackageResult<()>
+    where F: FnOnce(&Path) -> PackageResult<()>
+{
+    fs::create_dir_all(staging_path).map_err(|e| get_disk_error("create", staging_path, e))?;
+    if let Err(e) = extract(staging_path) {
+        let _ = fs::remove_dir_all(staging_path);
+        return Err(e);
+    }
+    if let Err(e) = mark_complete(staging_path, source) {
+        let _ = fs::remove_dir_all(staging_path);
+        return Err(e);
+    }
+    fs::rename(staging_path, output_directory).map_err(|e| {
+        let _ = fs::remove_dir_all(staging_path);
+        get_disk_error("move extracted files to", output_directory, e)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract_staged() {
+        let directory = std::env::temp_dir().join(format!("idf-env-staging-{}", std::process::id()));
+        let output = directory.join("llvm");
+        let orphan = directory.join(".llvm.staging-1");
+        fs::create_dir_all(&orphan).unwrap();
+        fs::create_dir_all(&output).unwrap();
+        fs::write(output.join("user-file"), "").unwrap();
+        assert!(!is_complete(&output) && is_installed(&output));
+
+        // Previous installation without marker is kept and nothing is extracted
+        extract_staged(&output, "llvm.tar.xz", |_| panic!("extracted over previous installation")).unwrap();
+        assert!(output.join("user-file").exists() && !orphan.exists() && !is_complete(&output));
+        fs::remove_file(output.join("user-file")).unwrap();
+        assert!(!is_installed(&output));
+
+        // Staging directory of a running extraction is kept
+        let live = directory.join(".llvm.staging-2");
+        fs::create_dir_all(&live).unwrap();
+        let live_lock = open_lock(&live).unwrap();
+        live_lock.lock_exclusive().unwrap();
+        remove_orphans(&output);
+        assert!(live.exists());
+        FileExt::unlock(&live_lock).unwrap();
+
+        let error = extract_staged(&output, "llvm.tar.xz", |staging| {
+            fs::write(staging.join("clang"), "binary")?;
+            Err(PackageError::Extraction("truncated archive".to_string()))
+        }).unwrap_err();
+        assert_eq!(error.exit_code(), super::super::error::EXIT_EXTRACTION);
+        assert!(!live.exists() && !output.exists());
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+
+        extract_staged(&output, "llvm.tar.xz", |staging| Ok(fs::write(staging.join("clang"), "binary")?)).unwrap();
+        assert!(is_complete(&output));
+        assert_eq!(fs::read_to_string(output.join("clang")).unwrap(), "binary");
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 1);
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
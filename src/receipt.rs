@@ -0,0 +1,173 @@
+mod store;
+
+use clap::Arg;
+use clap_nested::{Command, Commander, MultiCommand};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::config::get_tools_path;
+use crate::package::progress::format_duration;
+use crate::shell;
+pub use store::{EnvChange, Receipt};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+fn get_receipts_path() -> PathBuf {
+    Path::new(&get_tools_path()).join(store::RECEIPTS_FILE)
+}
+
+pub fn load_receipts() -> Result<Vec<Receipt>> {
+    store::load(&get_receipts_path())
+}
+
+pub fn load_receipts_or_exit() -> Vec<Receipt> {
+    match load_receipts() {
+        Ok(receipts) => receipts,
+        Err(e) => {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Store receipt of installed component, it replaces receipt of previous installation of the component.
+/// Installation is not failed when the receipt can't be written, the component just can't be uninstalled by receipt.
+pub fn record(receipt: Receipt) {
+    let key = receipt.get_key();
+    let result = store::update(&get_receipts_path(), |receipts| {
+        match receipts.iter_mut().find(|stored| stored.get_key() == key) {
+            Some(stored) => *stored = receipt,
+            None => receipts.push(receipt),
+        }
+    });
+    if let Err(e) = result {
+        println!("Unable to record installation of {}: {}", key, e);
+    }
+}
+
+fn record_env_change(group: &str, component: &str, change: EnvChange) {
+    let variable = change.variable.clone();
+    let result = store::update(&get_receipts_path(), |receipts| {
+        let receipt = receipts.iter_mut().find(|receipt| receipt.group == group && receipt.component == component);
+        if let Some(receipt) = receipt.filter(|receipt| !receipt.env.contains(&change)) {
+            receipt.env.push(change);
+        }
+    });
+    if let Err(e) = result {
+        println!("Unable to record change of {} by {}/{}: {}", variable, group, component, e);
+    }
+}
+
+/// Append value to PATH and record the change in receipt of the component, so that uninstall reverts it.
+pub fn update_env_path(group: &str, component: &str, value: &str) {
+    shell::update_env_path(value);
+    record_env_change(group, component, EnvChange { variable: "PATH".to_string(), value: value.to_string() });
+}
+
+/// Set user environment variable and record the change in receipt of the component, so that uninstall reverts it.
+/// Variables are persisted only on Windows, like PATH.
+pub fn set_env_variable(group: &str, component: &str, variable: &str, value: &str) {
+    #[cfg(windows)]
+    shell::set_env_variable(variable, value.to_string());
+    record_env_change(group, component, EnvChange { variable: variable.to_string(), value: value.to_string() });
+}
+
+fn remove_path(path: &str) -> bool {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_e) => return true,
+    };
+    println!("Removing: {}", path);
+    let result = if metadata.is_dir() { fs::remove_dir_all(path) } else { fs::remove_file(path) };
+    match result {
+        Ok(_) => true,
+        Err(e) => {
+            println!("Unable to remove {}: {}", path, e);
+            false
+        }
+    }
+}
+
+/// Remove files, directories and environment changes recorded for selected components and drop their receipts.
+/// Receipt is kept when some of its paths can't be removed, so that uninstall can be repeated.
+/// Returns false when no receipt was selected, e.g. for components installed by older idf-env.
+pub fn uninstall<F: Fn(&Receipt) -> bool>(selected: F) -> bool {
+    let result = store::update(&get_receipts_path(), |receipts| {
+        let (removed, kept): (Vec<Receipt>, Vec<Receipt>) = receipts.drain(..).partition(|receipt| selected(receipt));
+        *receipts = kept;
+        for receipt in &removed {
+            println!("Uninstalling {} {}", receipt.get_key(), receipt.version);
+            let failed = receipt.paths.iter().rev().filter(|path| !remove_path(path)).count();
+            // Directory on PATH may be shared with a component which stays installed
+            for change in receipt.env.iter().filter(|change| !receipts.iter().any(|kept| kept.env.contains(change))) {
+                shell::remove_env_variable_value(&change.variable, &change.value);
+            }
+            if failed > 0 {
+                receipts.push(Receipt { env: Vec::new(), ..receipt.clone() });
+            }
+        }
+        !removed.is_empty()
+    });
+    result.unwrap_or_else(|e| {
+        println!("Unable to update receipts: {}", e);
+        false
+    })
+}
+
+fn format_installed(receipt: &Receipt, now: SystemTime) -> String {
+    let age = now.duration_since(UNIX_EPOCH + Duration::from_secs(receipt.installed)).unwrap_or_default();
+    let days = age.as_secs() / (24 * 60 * 60);
+    if days > 0 { format!("{}d", days) } else { format_duration(age) }
+}
+
+fn get_list_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
+    let now = SystemTime::now();
+    let receipts: Vec<Receipt> = load_receipts_or_exit().into_iter()
+        .filter(|receipt| matches.value_of("group").is_none_or(|group| receipt.group == group))
+        .collect();
+    for receipt in &receipts {
+        println!("{} {} (installed {} ago)", receipt.get_key(), receipt.version, format_installed(receipt, now));
+        println!("  source: {}", receipt.source);
+        if !receipt.sha256.is_empty() {
+            println!("  sha256: {}", receipt.sha256);
+        }
+        for path in &receipt.paths {
+            let state = if Path::new(path).exists() { "" } else { " (missing)" };
+            println!("  path: {}{}", path, state);
+        }
+        for change in &receipt.env {
+            println!("  env: {}+={}", change.variable, change.value);
+        }
+    }
+    println!("{} installed components.", receipts.len());
+    Ok(())
+}
+
+pub fn get_list_cmd<'a>() -> Command<'a, str> {
+    Command::new("list")
+        .description("List components installed by idf-env with version, source, digest, paths and environment changes")
+        .options(|app| {
+            app.arg(
+                Arg::with_name("group")
+                    .short("g")
+                    .long("group")
+                    .help("List only components installed by the command, e.g. rust, toit or idf")
+                    .takes_value(true)
+            )
+        })
+        .runner(|_args, matches|
+            get_list_runner(_args, matches)
+        )
+}
+
+pub fn get_multi_cmd<'a>() -> MultiCommand<'a, str, str> {
+    let multi_cmd: MultiCommand<str, str> = Commander::new()
+        .add_cmd(get_list_cmd())
+        .into_cmd("receipt")
+
+        // Optionally specify a description
+        .description("Receipts of components installed by idf-env.");
+
+    return multi_cmd;
+}
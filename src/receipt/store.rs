@@ -0,0 +1,183 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use json::JsonValue;
+
+use crate::config::storage::{write_atomic, ConfigLock};
+use crate::package::checksum::get_recorded_digest;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Receipts are stored in this file in IDF_TOOLS_PATH.
+pub const RECEIPTS_FILE: &str = "idf-env-receipts.json";
+
+/// Version of receipts file format written by this build of idf-env.
+pub const RECEIPTS_VERSION: u32 = 1;
+
+/// Value added to environment variable by installation, e.g. directory appended to PATH.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnvChange {
+    pub variable: String,
+    pub value: String,
+}
+
+/// Everything deployed by one installation of a component, so that it can be listed and removed later.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Receipt {
+    /// Command which installed the component, e.g. rust, toit or idf.
+    pub group: String,
+    /// Name unique within the group, e.g. llvm or cargo-espflash.
+    pub component: String,
+    pub version: String,
+    /// URL of the downloaded archive.
+    pub source: String,
+    /// SHA-256 of the archive recorded in dist/SHA256SUMS, empty when unknown.
+    pub sha256: String,
+    /// Unix time of the installation.
+    pub installed: u64,
+    /// Files and directories created by the installation.
+    pub paths: Vec<String>,
+    pub env: Vec<EnvChange>,
+}
+
+impl Receipt {
+    pub fn new(group: &str, component: &str, version: &str, source: &str) -> Receipt {
+        Receipt {
+            group: group.to_string(),
+            component: component.to_string(),
+            version: version.to_string(),
+            source: source.to_string(),
+            installed: SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0),
+            ..Default::default()
+        }
+    }
+
+    /// Take digest of the archive from manifest of the dist directory.
+    pub fn with_archive(mut self, archive: &str) -> Receipt {
+        self.sha256 = get_recorded_digest(Path::new(archive)).unwrap_or_default();
+        self
+    }
+
+    pub fn with_path(mut self, path: &str) -> Receipt {
+        self.paths.push(path.to_string());
+        self
+    }
+
+    /// Identification in form <group>/<component>.
+    pub fn get_key(&self) -> String {
+        format!("{}/{}", self.group, self.component)
+    }
+
+    fn from_json(value: &JsonValue) -> Receipt {
+        let get_string = |key: &str| value[key].as_str().unwrap_or("").to_string();
+        Receipt {
+            group: get_string("group"),
+            component: get_string("component"),
+            version: get_string("version"),
+            source: get_string("source"),
+            sha256: get_string("sha256"),
+            installed: value["installed"].as_u64().unwrap_or(0),
+            paths: value["paths"].members().filter_map(|path| path.as_str()).map(|path| path.to_string()).collect(),
+            env: value["env"].members()
+                .map(|change| EnvChange {
+                    variable: change["variable"].as_str().unwrap_or("").to_string(),
+                    value: change["value"].as_str().unwrap_or("").to_string(),
+                })
+                .filter(|change| !change.variable.is_empty())
+                .collect(),
+        }
+    }
+
+    fn to_json(&self) -> JsonValue {
+        let mut value = json::object! {
+            group: self.group.clone(),
+            component: self.component.clone(),
+            version: self.version.clone(),
+            source: self.source.clone(),
+            sha256: self.sha256.clone(),
+            installed: self.installed,
+            paths: self.paths.clone(),
+            env: JsonValue::new_array()
+        };
+        for change in &self.env {
+            let _ = value["env"].push(json::object! { variable: change.variable.clone(), value: change.value.clone() });
+        }
+        value
+    }
+}
+
+/// Receipts from content of receipts file. Receipts without group or component are dropped.
+pub fn parse(content: &str) -> Result<Vec<Receipt>> {
+    let document = json::parse(content)?;
+    let version = document["receiptsVersion"].as_u32().unwrap_or(0);
+    if version > RECEIPTS_VERSION {
+        return Err(format!("Receipts version {} is newer than supported version {}. Please, update idf-env.",
+                           version, RECEIPTS_VERSION).into());
+    }
+    Ok(document["receipts"].members()
+        .map(Receipt::from_json)
+        .filter(|receipt| !receipt.group.is_empty() && !receipt.component.is_empty())
+        .collect())
+}
+
+pub fn dump(receipts: &[Receipt]) -> String {
+    let mut document = json::object! {
+        receiptsVersion: RECEIPTS_VERSION,
+        receipts: JsonValue::new_array()
+    };
+    for receipt in receipts {
+        let _ = document["receipts"].push(receipt.to_json());
+    }
+    json::stringify_pretty(document, 2)
+}
+
+/// Receipts stored in the file, missing file means that nothing was installed yet.
+pub fn load(path: &Path) -> Result<Vec<Receipt>> {
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    let content = fs::read_to_string(path)
+        .map_err(|e| format!("Unable to read {}: {}", path.display(), e))?;
+    parse(&content).map_err(|e| format!("Unable to parse {}: {}", path.display(), e).into())
+}
+
+/// Apply modification to the receipts and store the result.
+/// Whole read-modify-write cycle runs under the lock of the receipts file.
+pub fn update<T, F: FnOnce(&mut Vec<Receipt>) -> T>(path: &Path, modify: F) -> Result<T> {
+    let path_text = path.display().to_string();
+    let _lock = ConfigLock::acquire(&path_text)?;
+    let mut receipts = load(path)?;
+    let result = modify(&mut receipts);
+    write_atomic(&path_text, &dump(&receipts))?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_update_and_load() {
+        let directory = std::env::temp_dir().join(format!("idf-env-receipts-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(RECEIPTS_FILE);
+        assert!(load(&path).unwrap().is_empty());
+
+        let mut receipt = Receipt::new("toit", "jaguar", "latest", "https://github.com/toitlang/jaguar/releases/latest/download/jag_windows.zip")
+            .with_path("C:/Users/esp/AppData/Local/Programs/jaguar");
+        receipt.env.push(EnvChange { variable: "PATH".to_string(), value: "C:/Users/esp/AppData/Local/Programs/jaguar".to_string() });
+        update(&path, |receipts| receipts.push(receipt.clone())).unwrap();
+        assert_eq!(load(&path).unwrap(), vec![receipt.clone()]);
+        assert_eq!(receipt.get_key(), "toit/jaguar");
+
+        fs::write(&path, r#"{"receiptsVersion": 1, "receipts": [{"group": "rust"}, {"group": "rust", "component": "llvm", "paths": ["llvm", 1]}]}"#).unwrap();
+        let receipts = load(&path).unwrap();
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].paths, vec!["llvm".to_string()]);
+
+        fs::write(&path, r#"{"receiptsVersion": 2, "receipts": []}"#).unwrap();
+        assert!(load(&path).is_err());
+        fs::remove_dir_all(directory).unwrap();
+    }
+}
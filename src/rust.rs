@@ -5,9 +5,12 @@ use dirs::home_dir;
 use std::path::Path;
 use std::fs::{remove_dir_all, copy};
 use std::process::Stdio;
-use crate::config::get_tool_path;
-use crate::package::{prepare_package_strip_prefix, prepare_package, prepare_single_binary};
-use crate::shell::{run_command, update_env_path};
+use crate::config::{get_dist_path, get_tool_path};
+use crate::package::{prepare_downloaded_package_strip_prefix, prepare_downloaded_package, prepare_single_binary, PackageError};
+use crate::package::offline::{is_dry_run, is_offline};
+use crate::package::staging::{is_installed, mark_complete};
+use crate::package::scheduler::{get_parallelism, Scheduler};
+use crate::receipt::{self, Receipt};
 
 const DEFAULT_RUST_TOOLCHAIN_VERSION:&str = "1.63.0.0";
 const DEFAULT_LLVM_VERSION:&str = "esp-14.0.0-20220415";
@@ -22,10 +25,10 @@ struct RustCrate {
 
 struct RustToolchain {
     arch: String,
-    //llvm_release: String,
+    llvm_release: String,
     //llvm_arch: String,
     //artifact_file_extension: String,
-    // version: String,
+    version: String,
     rust_dist: String,
     rust_dist_temp: String,
     rust_src_dist: String,
@@ -41,6 +44,7 @@ struct RustToolchain {
     idf_tool_xtensa_elf_clang: String,
     extra_tools: String,
     extra_crates: Vec<RustCrate>,
+    mingw_release: String,
     mingw_url: String,
     mingw_dist_file: String,
     mingw_destination_directory: String
@@ -160,6 +164,15 @@ fn get_rust_crate(name: &str, arch: &str) -> Option<RustCrate> {
     }
 }
 
+/// Version from release URL like .../releases/download/v0.16.0/..., releases/latest is recorded as latest.
+fn get_crate_version(url: &str) -> String {
+    match url.split("/releases/").nth(1).map(|release| release.split('/').collect::<Vec<&str>>()) {
+        Some(parts) if parts.first() == Some(&"latest") => "latest".to_string(),
+        Some(parts) if parts.len() > 1 => parts[1].trim_start_matches('v').to_string(),
+        _ => "".to_string(),
+    }
+}
+
 fn get_extra_crates(crates_list: &str, arch:&str) -> Vec<RustCrate> {
     crates_list.split(",").into_iter().filter_map(|s| { get_rust_crate(s, arch) }).collect()
 }
@@ -178,19 +191,16 @@ fn build_rust_toolchain(version:&str, llvm_version: &str, arch:&str, extra_tools
     let llvm_url = format!("https://github.com/espressif/llvm-project/releases/download/{}/{}", llvm_release, llvm_file);
     let idf_tool_xtensa_elf_clang = format!("{}/{}-{}", get_tool_path("xtensa-esp32-elf-clang".to_string()), llvm_release, arch);
     let mingw_release = "x86_64-12.1.0-release-posix-seh-rt_v10-rev3".to_string();
-    let mingw_dist_file = format!("{}.zip", mingw_release);
-    // Temporal solution - repackaging 7z to zip, because Rust based decompression crate does not have BCJ support: https://github.com/dyz1990/sevenz-rust/issues/1
-    //let mingw_dist_file = format!("{}.7z", mingw_release);
-    //let mingw_url = format!("https://github.com/niXman/mingw-builds-binaries/releases/download/12.1.0-rt_v10-rev3/{}", mingw_dist_file);
-    let mingw_url = format!("https://github.com/esp-rs/rust-build/releases/download/mingw-12/{}", mingw_dist_file);
+    let mingw_dist_file = format!("{}.7z", mingw_release);
+    let mingw_url = format!("https://github.com/niXman/mingw-builds-binaries/releases/download/12.1.0-rt_v10-rev3/{}", mingw_dist_file);
     let mingw_destination_directory = format!("{}/{}", get_tool_path("mingw".to_string()), mingw_release);
 
     RustToolchain {
         arch: arch.to_string(),
-        //llvm_release,
+        llvm_release,
         //llvm_arch,
         //artifact_file_extension,
-        //version: version.to_string(),
+        version: version.to_string(),
         rust_dist,
         rust_dist_temp: get_tool_path("rust".to_string()),
         rust_src_dist,
@@ -206,6 +216,7 @@ fn build_rust_toolchain(version:&str, llvm_version: &str, arch:&str, extra_tools
         idf_tool_xtensa_elf_clang,
         extra_tools: extra_tools.to_string(),
         extra_crates: get_extra_crates(extra_crates_list, arch),
+        mingw_release,
         mingw_url,
         mingw_dist_file,
         mingw_destination_directory
@@ -213,10 +224,14 @@ fn build_rust_toolchain(version:&str, llvm_version: &str, arch:&str, extra_tools
 }
 
 
+const RUSTUP_INIT_URL: &str = "https://win.rustup.rs/x86_64";
+const VS_BUILD_TOOLS_URL: &str = "https://aka.ms/vs/17/release/vs_buildtools.exe";
+
 fn install_rust_stable(default_host: &str) {
-    let rustup_init_path = prepare_single_binary("https://win.rustup.rs/x86_64",
+    let rustup_init_path = prepare_single_binary(RUSTUP_INIT_URL,
                          "rustup-init.exe",
-                          "rustup");
+                          "rustup",
+                          None).unwrap_or_else(|e| e.exit("Unable to prepare package: "));
     println!("rustup stable");
     match std::process::Command::new(rustup_init_path)
         .arg("--default-toolchain")
@@ -266,68 +281,80 @@ fn install_rust(default_host: &str) {
     install_rust_nightly();
 }
 
-fn install_mingw(toolchain:&RustToolchain) {
-    if Path::new(toolchain.mingw_destination_directory.as_str()).exists() {
+fn schedule_mingw<'a>(scheduler: &mut Scheduler<'a>, toolchain: &'a RustToolchain) {
+    if is_installed(Path::new(&toolchain.mingw_destination_directory)) {
         println!("Previous installation of MinGW exist in: {}", toolchain.mingw_destination_directory);
         println!("Please, remove the directory before new installation.");
         return;
     }
 
-    match prepare_package_strip_prefix(&toolchain.mingw_url,
-        &toolchain.mingw_dist_file,
-        toolchain.mingw_destination_directory.clone(),
-        "mingw64"
-) {
-Ok(_) => { println!("Package ready"); },
-Err(_e) => { println!("Unable to prepare package"); }
+    scheduler.add("mingw", &toolchain.mingw_url, &get_dist_path(&toolchain.mingw_dist_file), None, &[], move || {
+        prepare_downloaded_package_strip_prefix(&toolchain.mingw_dist_file,
+                                                toolchain.mingw_destination_directory.clone(),
+                                                "mingw64")?;
+        receipt::record(Receipt::new("rust", "mingw", &toolchain.mingw_release, &toolchain.mingw_url)
+            .with_archive(&get_dist_path(&toolchain.mingw_dist_file))
+            .with_path(&toolchain.mingw_destination_directory));
+        Ok(())
+    });
 }
+
+fn install_binary_crate(extra_crate: &RustCrate) -> Result<(), PackageError> {
+    let tmp_path = get_tool_path(extra_crate.name.to_string());
+    prepare_downloaded_package(
+        &extra_crate.dist_file,
+        tmp_path
+    )?;
+    let source = format!("{}/{}", get_tool_path(extra_crate.name.to_string()), extra_crate.dist_bin);
+    match copy(source.clone(), extra_crate.bin.to_string()) {
+        Ok(_) => {
+            println!("Create {} installed.", extra_crate.name);
+            receipt::record(Receipt::new("rust", &extra_crate.name, &get_crate_version(&extra_crate.url), &extra_crate.url)
+                .with_archive(&get_dist_path(&extra_crate.dist_file))
+                .with_path(&get_tool_path(extra_crate.name.to_string()))
+                .with_path(&extra_crate.bin));
+        },
+        Err(_e) => {
+            println!("Unable to copy crate binary from {} to {}", source, extra_crate.bin)
+        }
+    }
+    Ok(())
+}
+
+// Binary crates are downloaded by the scheduler together with the toolchain, crates without binary
+// release are built from source code once the toolchain is ready
+fn schedule_binary_crates<'a>(scheduler: &mut Scheduler<'a>, extra_crates: &'a [RustCrate], dependencies: &[&str]) {
+    for extra_crate in extra_crates.iter().filter(|extra_crate| !extra_crate.url.is_empty()) {
+        scheduler.add(&extra_crate.name, &extra_crate.url, &get_dist_path(&extra_crate.dist_file), None, dependencies, move || {
+            install_binary_crate(extra_crate)
+        });
+    }
 }
 
-fn install_extra_crates(extra_crates:&Vec<RustCrate>) {
-    for extra_crate in extra_crates.into_iter() {
+fn install_source_crates(extra_crates: &[RustCrate]) {
+    for extra_crate in extra_crates.iter().filter(|extra_crate| extra_crate.url.is_empty()) {
         println!("Installing crate {}", extra_crate.name);
 
-        if extra_crate.url.is_empty() {
-            // Binary crate is not available, install from source code
-            let cargo_path = format!("{}/bin/cargo.exe", get_cargo_home());
-
-            println!("{} install {}", cargo_path, extra_crate.name);
-            match std::process::Command::new(cargo_path)
-                .arg("install")
-                .arg(extra_crate.name.to_string())
-                .stdout(Stdio::piped())
-                .output()
-            {
-                Ok(child_output) => {
-                    let result = String::from_utf8_lossy(&child_output.stdout);
-                    println!("Crate installed: {}", result);
-                }
-                Err(e) => {
-                    println!("Crate installation failed: {}", e);
+        let cargo_path = format!("{}/bin/cargo.exe", get_cargo_home());
+
+        println!("{} install {}", cargo_path, extra_crate.name);
+        match std::process::Command::new(cargo_path)
+            .arg("install")
+            .arg(extra_crate.name.to_string())
+            .stdout(Stdio::piped())
+            .output()
+        {
+            Ok(child_output) => {
+                let result = String::from_utf8_lossy(&child_output.stdout);
+                println!("Crate installed: {}", result);
+                if child_output.status.success() {
+                    receipt::record(Receipt::new("rust", &extra_crate.name, "", &format!("https://crates.io/crates/{}", extra_crate.name))
+                        .with_path(&extra_crate.bin));
                 }
             }
-
-        } else {
-            // Binary crate is available donwload it
-            let tmp_path = get_tool_path(extra_crate.name.to_string());
-            match prepare_package(
-                extra_crate.url.to_string(),
-                &extra_crate.dist_file,
-                tmp_path
-            ) {
-                Ok(_) => {
-                    let source = format!("{}/{}", get_tool_path(extra_crate.name.to_string()), extra_crate.dist_bin);
-                    match copy(source.clone(), extra_crate.bin.to_string()) {
-                        Ok(_) => {
-                            println!("Create {} installed.", extra_crate.name);
-                        },
-                        Err(_e) => {
-                            println!("Unable to copy crate binary from {} to {}", source, extra_crate.bin)
-                        }
-                    }
-                },
-                Err(_e) => { println!("Unable to unpack bianry crate {}.", extra_crate.name); }
-            };
+            Err(e) => {
+                println!("Crate installation failed: {}", e);
+            }
         }
     }
 }
@@ -339,9 +366,10 @@ fn install_vctools() {
     // .\vs_BuildTools.exe --passive --wait --add Microsoft.VisualStudio.Component.VC.Tools.x86.x64 --add Microsoft.VisualStudio.Component.Windows10SDK.20348
     // path C:\Program Files (x86)\Microsoft Visual Studio\2022\BuildTools\VC\Tools\MSVC\14.33.31629\bin\Hostx64\x64
 
-    let vs_build_tools = prepare_single_binary("https://aka.ms/vs/17/release/vs_buildtools.exe",
+    let vs_build_tools = prepare_single_binary(VS_BUILD_TOOLS_URL,
                          "vs_buildtools.exe",
-                          "vs_buildtools");
+                          "vs_buildtools",
+                          None).unwrap_or_else(|e| e.exit("Unable to prepare package: "));
     println!("Running VS BuildTools: vs_BuildTools.exe --passive --wait --add Microsoft.VisualStudio.Component.VC.Tools.x86.x64 --add Microsoft.VisualStudio.Component.Windows10SDK.20348");
 
     match std::process::Command::new(vs_build_tools)
@@ -364,7 +392,20 @@ fn install_vctools() {
     }
 }
 
-fn install_rust_toolchain(toolchain:&RustToolchain) {
+/// What has to be done with rustup of the host.
+#[derive(PartialEq)]
+enum RustupState {
+    /// rustup-init is scheduled, it installs stable and nightly toolchains.
+    Scheduled,
+    /// Stable toolchain exists, rustup installs nightly after the download check.
+    NightlyMissing,
+    Ready,
+}
+
+// rustup-init is downloaded together with other packages, stable toolchain is installed
+// by rustup-init before any other package.
+fn schedule_rustup<'a>(scheduler: &mut Scheduler<'a>, toolchain: &'a RustToolchain) -> RustupState {
+    let rustup_init_path = format!("{}/rustup-init.exe", get_tool_path("rustup".to_string()));
     match std::process::Command::new("rustup")
         .arg("toolchain")
         .arg("list")
@@ -372,26 +413,63 @@ fn install_rust_toolchain(toolchain:&RustToolchain) {
         .output() {
         Ok(child_output) => {
             println!("rustup - found");
-            let result = String::from_utf8_lossy(&child_output.stdout);
+            let result = String::from_utf8_lossy(&child_output.stdout).to_string();
+            println!("rustup - found - {}", result);
+            let nightly_missing = !result.contains("nightly");
             if !result.contains("stable") {
                 println!("stable toolchain not found");
-                install_rust_stable(&toolchain.arch);
+                scheduler.add("rustup", RUSTUP_INIT_URL, &rustup_init_path, None, &[], move || {
+                    install_rust_stable(&toolchain.arch);
+                    if nightly_missing {
+                        println!("nightly toolchain not found");
+                        install_rust_nightly();
+                    }
+                    Ok(())
+                });
+                return RustupState::Scheduled;
             }
-            if !result.contains("nightly") {
+            if nightly_missing {
                 println!("nightly toolchain not found");
-                install_rust_nightly();
+                return RustupState::NightlyMissing;
             }
-            println!("rustup - found - {}", String::from_utf8_lossy(&child_output.stdout));
         },
         Err(e) => {
             if let std::io::ErrorKind::NotFound = e.kind() {
                 println!("rustup was not found.");
-                install_rust(&toolchain.arch);
+                scheduler.add("rustup", RUSTUP_INIT_URL, &rustup_init_path, None, &[], move || {
+                    install_rust(&toolchain.arch);
+                    Ok(())
+                });
+                return RustupState::Scheduled;
             }
         },
     }
+    RustupState::Ready
+}
 
-    if Path::new(toolchain.destination_dir.as_str()).exists() {
+/// Run install.sh of extracted rust-build distribution. Failed installer stops the installation,
+/// so that packages which depend on it are not installed.
+fn run_installer(installer: &str, destination_dir: &str) -> Result<(), PackageError> {
+    let output = std::process::Command::new("/bin/bash")
+        .arg("-c")
+        .arg(format!("{} --destdir={} --prefix='' --without=rust-docs", installer, destination_dir))
+        .output()
+        .map_err(|e| PackageError::Extraction(format!("Unable to run {}: {}", installer, e)))?;
+    if !output.status.success() {
+        return Err(PackageError::Extraction(format!("{} failed with {}: {}", installer, output.status,
+                                                    String::from_utf8_lossy(&output.stderr).trim())));
+    }
+    println!("Command succeeded");
+    Ok(())
+}
+
+fn install_rust_toolchain(toolchain:&RustToolchain, parallelism: usize) {
+    // Archives are downloaded concurrently, each package is extracted when its archive and packages it depends on are ready
+    let mut scheduler = Scheduler::new(parallelism);
+    let rustup_state = schedule_rustup(&mut scheduler, toolchain);
+    let rustup_dependency: &[&str] = if rustup_state == RustupState::Scheduled { &["rustup"] } else { &[] };
+
+    if is_installed(Path::new(&toolchain.destination_dir)) {
         println!("Previous installation of Rust Toolchain exist in: {}", toolchain.destination_dir);
         println!("Please, remove the directory before new installation.");
     } else {
@@ -399,65 +477,90 @@ fn install_rust_toolchain(toolchain:&RustToolchain) {
         // Some platfroms like Windows are available in single bundle rust + src, because install
         // script in dist is not available for the plaform. It's sufficient to extract the toolchain
         if toolchain.rust_installer.is_empty() {
-            match prepare_package_strip_prefix(&toolchain.rust_dist_url,
-                                         &toolchain.rust_dist_file,
-                                         toolchain.destination_dir.to_string(),
-                                         "esp") {
-                                            Ok(_) => { println!("Package ready"); },
-                                            Err(_e) => { println!("Unable to prepare package"); }
-                                        }
+            scheduler.add("rust", &toolchain.rust_dist_url, &get_dist_path(&toolchain.rust_dist_file), None, rustup_dependency, move || {
+                prepare_downloaded_package_strip_prefix(&toolchain.rust_dist_file,
+                                                        toolchain.destination_dir.to_string(),
+                                                        "esp")?;
+                receipt::record(Receipt::new("rust", "rust", &toolchain.version, &toolchain.rust_dist_url)
+                    .with_archive(&get_dist_path(&toolchain.rust_dist_file))
+                    .with_path(&toolchain.destination_dir));
+                Ok(())
+            });
         } else {
-            match prepare_package_strip_prefix(&toolchain.rust_dist_url,
-                                         &toolchain.rust_dist_file,
-                                         toolchain.rust_dist_temp.to_string(),
-                                         toolchain.rust_dist.as_str()) {
-                                            Ok(_) => { println!("Package ready"); },
-                                            Err(_e) => { println!("Unable to prepare package"); }
-                                        }
-
-            let mut arguments: Vec<String> = [].to_vec();
-
-            arguments.push("-c".to_string());
-            arguments.push(format!("/tmp/rust/install.sh --destdir={} --prefix='' --without=rust-docs", toolchain.destination_dir));
-
-            match run_command("/bin/bash".to_string(), arguments.clone(), "".to_string()) {
-                Ok(_) => { println!("Command succeeded"); },
-                Err(_e) => { println!("Command failed"); }
-            }
-
-            match prepare_package_strip_prefix(&toolchain.rust_src_dist_url,
-                                         &toolchain.rust_src_dist_file,
-                                         toolchain.rust_src_dist_temp.to_string(),
-                                         toolchain.rust_src_dist.as_str()) {
-                                            Ok(_) => { println!("Package ready"); },
-                                            Err(_e) => { println!("Unable to prepare package"); }
-                                        }
-
-            let mut arguments: Vec<String> = [].to_vec();
-
-            arguments.push("-c".to_string());
-            arguments.push(format!("/tmp/rust-src/install.sh --destdir={} --prefix='' --without=rust-docs", toolchain.destination_dir));
-
-            match run_command("/bin/bash".to_string(), arguments, "".to_string()) {
-                Ok(_) => { println!("Command succeeded"); },
-                Err(_e) => { println!("Command failed"); }
-            }
-
+            scheduler.add("rust", &toolchain.rust_dist_url, &get_dist_path(&toolchain.rust_dist_file), None, rustup_dependency, move || {
+                prepare_downloaded_package_strip_prefix(&toolchain.rust_dist_file,
+                                                        toolchain.rust_dist_temp.to_string(),
+                                                        toolchain.rust_dist.as_str())?;
+
+                run_installer("/tmp/rust/install.sh", &toolchain.destination_dir)?;
+                receipt::record(Receipt::new("rust", "rust", &toolchain.version, &toolchain.rust_dist_url)
+                    .with_archive(&get_dist_path(&toolchain.rust_dist_file))
+                    .with_path(&toolchain.rust_dist_temp)
+                    .with_path(&toolchain.destination_dir));
+                Ok(())
+            });
+
+            // rust-src is installed into the toolchain directory created by the installer of rust
+            scheduler.add("rust-src", &toolchain.rust_src_dist_url, &get_dist_path(&toolchain.rust_src_dist_file), None, &["rust"], move || {
+                prepare_downloaded_package_strip_prefix(&toolchain.rust_src_dist_file,
+                                                        toolchain.rust_src_dist_temp.to_string(),
+                                                        toolchain.rust_src_dist.as_str())?;
+
+                run_installer("/tmp/rust-src/install.sh", &toolchain.destination_dir)?;
+                // Toolchain filled by installers is complete when rust-src is installed
+                mark_complete(Path::new(&toolchain.destination_dir), &toolchain.rust_dist_url)?;
+                // Files of rust-src are installed into the toolchain directory recorded by receipt of rust
+                receipt::record(Receipt::new("rust", "rust-src", &toolchain.version, &toolchain.rust_src_dist_url)
+                    .with_archive(&get_dist_path(&toolchain.rust_src_dist_file))
+                    .with_path(&toolchain.rust_src_dist_temp));
+                Ok(())
+            });
         }
     }
 
-    if Path::new(toolchain.idf_tool_xtensa_elf_clang.as_str()).exists() {
+    if is_installed(Path::new(&toolchain.idf_tool_xtensa_elf_clang)) {
         println!("Previous installation of LLVM exist in: {}", toolchain.idf_tool_xtensa_elf_clang);
         println!("Please, remove the directory before new installation.");
     } else {
-        match prepare_package_strip_prefix(&toolchain.llvm_url,
-                                     &toolchain.llvm_file,
-                                     toolchain.idf_tool_xtensa_elf_clang.clone(),
-                                     "xtensa-esp32-elf-clang"
-        ) {
-            Ok(_) => { println!("Package ready"); },
-            Err(_e) => { println!("Unable to prepare package"); }
-        }
+        scheduler.add("llvm", &toolchain.llvm_url, &get_dist_path(&toolchain.llvm_file), None, &[], move || {
+            prepare_downloaded_package_strip_prefix(&toolchain.llvm_file,
+                                                    toolchain.idf_tool_xtensa_elf_clang.clone(),
+                                                    "xtensa-esp32-elf-clang")?;
+            receipt::record(Receipt::new("rust", "llvm", &toolchain.llvm_release, &toolchain.llvm_url)
+                .with_archive(&get_dist_path(&toolchain.llvm_file))
+                .with_path(&toolchain.idf_tool_xtensa_elf_clang));
+            Ok(())
+        });
+    }
+
+    if toolchain.extra_tools == "mingw" && toolchain.arch == "x86_64-pc-windows-gnu" {
+        schedule_mingw(&mut scheduler, toolchain);
+    }
+    if toolchain.extra_tools == "vctools" {
+        let vs_build_tools_path = format!("{}/vs_buildtools.exe", get_tool_path("vs_buildtools".to_string()));
+        let bootstrapper_path = vs_build_tools_path.clone();
+        scheduler.add("vctools", VS_BUILD_TOOLS_URL, &vs_build_tools_path, None, &[], move || {
+            install_vctools();
+            // Build tools are removed by Visual Studio Installer, the receipt only records the bootstrapper and PATH
+            receipt::record(Receipt::new("rust", "vctools", "2022", VS_BUILD_TOOLS_URL)
+                .with_path(&bootstrapper_path));
+            Ok(())
+        });
+    }
+    // Binary crates are copied to .cargo/bin created by rustup
+    schedule_binary_crates(&mut scheduler, &toolchain.extra_crates, rustup_dependency);
+
+    // rustup downloads nightly toolchain on its own, offline installation can't continue without it
+    if rustup_state == RustupState::NightlyMissing && is_offline() && !is_dry_run() {
+        let e = PackageError::Unavailable("Offline mode: nightly toolchain is not installed and rustup can't download it".to_string());
+        e.exit("Unable to prepare package: ");
+    }
+    match scheduler.run() {
+        Ok(_) => { println!("Packages ready"); },
+        Err(e) => { e.exit("Unable to prepare package: "); }
+    }
+    if rustup_state == RustupState::NightlyMissing {
+        install_rust_nightly();
     }
 
     println!("Updating environment variables:");
@@ -468,7 +571,7 @@ fn install_rust_toolchain(toolchain:&RustToolchain) {
     #[cfg(unix)]
     println!("export PATH=\"{}:$PATH\"", libclang_bin);
 
-    update_env_path(&libclang_bin);
+    receipt::update_env_path("rust", "llvm", &libclang_bin);
 
     // It seems that LIBCLANG_PATH is not necessary for Windows
     // let libclang_path = format!("{}/libclang.dll", libclang_bin);
@@ -481,34 +584,34 @@ fn install_rust_toolchain(toolchain:&RustToolchain) {
         "mingw" => {
             match toolchain.arch.as_str() {
                 "x86_64-pc-windows-gnu" => {
-                    install_mingw(toolchain);
-                    update_env_path(format!("{}/bin", toolchain.mingw_destination_directory).as_str());
+                    receipt::update_env_path("rust", "mingw", format!("{}/bin", toolchain.mingw_destination_directory).as_str());
                 }
                 _ => { println!("Ok"); }
             }
         },
         "vctools" => {
-            install_vctools();
-            update_env_path("C:\\Program Files (x86)\\Microsoft Visual Studio\\2022\\BuildTools\\VC\\Tools\\MSVC\\14.33.31629\\bin\\Hostx64\\x64");
+            receipt::update_env_path("rust", "vctools", "C:\\Program Files (x86)\\Microsoft Visual Studio\\2022\\BuildTools\\VC\\Tools\\MSVC\\14.33.31629\\bin\\Hostx64\\x64");
         }
         _ => { println!("No extra tools selected"); }
     }
 
-    install_extra_crates(&toolchain.extra_crates);
+    install_source_crates(&toolchain.extra_crates);
 }
 
 fn uninstall_rust_toolchain(toolchain:&RustToolchain) {
-    if Path::new(toolchain.destination_dir.as_str()).exists() {
-        println!("Removing: {}", toolchain.destination_dir);
-        match remove_dir_all(&toolchain.destination_dir) {
-            Ok(_) => { println!("Removed."); },
-            Err(_e) => { println!("Failed to remove."); }
+    let recorded: Vec<String> = receipt::load_receipts_or_exit().into_iter()
+        .filter(|receipt| receipt.group == "rust")
+        .map(|receipt| receipt.component)
+        .collect();
+    receipt::uninstall(|receipt| receipt.group == "rust");
+
+    // Components installed by older idf-env without receipts
+    for (component, directory) in [("rust", &toolchain.destination_dir), ("llvm", &toolchain.idf_tool_xtensa_elf_clang)] {
+        if recorded.iter().any(|recorded| recorded == component) || !Path::new(directory).exists() {
+            continue;
         }
-    }
-
-    if Path::new(toolchain.idf_tool_xtensa_elf_clang.as_str()).exists() {
-        println!("Removing: {}", toolchain.idf_tool_xtensa_elf_clang);
-        match remove_dir_all(&toolchain.idf_tool_xtensa_elf_clang) {
+        println!("Removing: {}", directory);
+        match remove_dir_all(directory) {
             Ok(_) => { println!("Removed."); },
             Err(_e) => { println!("Failed to remove."); }
         }
@@ -539,7 +642,7 @@ fn get_default_rust_toolchain(matches: &clap::ArgMatches<'_>) -> RustToolchain {
 fn get_install_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::result::Result<(), clap::Error> {
     let toolchain = get_default_rust_toolchain(matches);
 
-    install_rust_toolchain(&toolchain);
+    install_rust_toolchain(&toolchain, get_parallelism(matches.value_of("jobs")));
     Ok(())
 }
 
@@ -547,7 +650,7 @@ fn get_reinstall_runner(_args: &str, matches: &clap::ArgMatches<'_>) -> std::res
     let toolchain = get_default_rust_toolchain(matches);
 
     uninstall_rust_toolchain(&toolchain);
-    install_rust_toolchain(&toolchain);
+    install_rust_toolchain(&toolchain, get_parallelism(matches.value_of("jobs")));
     Ok(())
 }
 
@@ -602,6 +705,13 @@ pub fn get_install_cmd<'a>() -> Command<'a, str> {
                         .takes_value(true)
                         .default_value("")
                 )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .help("Number of concurrent downloads, default IDF_ENV_DOWNLOAD_PARALLELISM or 4")
+                        .takes_value(true)
+                )
 
         })
         .runner(|_args, matches|
@@ -637,6 +747,13 @@ pub fn get_reinstall_cmd<'a>() -> Command<'a, str> {
                         .takes_value(true)
                         .default_value(guess_host_triple::guess_host_triple().unwrap())
                 )
+                .arg(
+                    Arg::with_name("jobs")
+                        .short("j")
+                        .long("jobs")
+                        .help("Number of concurrent downloads, default IDF_ENV_DOWNLOAD_PARALLELISM or 4")
+                        .takes_value(true)
+                )
 
         })
         .runner(|_args, matches|
@@ -708,4 +825,12 @@ mod tests {
         assert_eq!(extra_crates.len(), 5);
     }
 
+    #[test]
+    fn test_get_crate_version() {
+        let extra_crates = get_extra_crates("cargo-espflash,cargo-generate", "x86_64-pc-windows-msvc");
+        assert_eq!(get_crate_version(&extra_crates[0].url), "latest");
+        assert_eq!(get_crate_version(&extra_crates[1].url), "0.16.0");
+        assert_eq!(get_crate_version(""), "");
+    }
+
 }
\ No newline at end of file
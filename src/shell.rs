@@ -51,6 +51,7 @@ pub fn run_command(shell: String, arguments: Vec<String>, command: String) -> st
     Ok(())
 }
 
+#[cfg(windows)]
 pub fn wide_null(s: &str) -> Vec<u16> {
     s.encode_utf16().chain(Some(0)).collect()
 }
@@ -73,6 +74,7 @@ pub fn set_env_variable(key:&str, value:String) {
     }
 }
 
+#[cfg(any(windows, test))]
 fn append_path(original_path: &str, new_path: &str) -> String {
     if original_path.len() == 0 {
         return new_path.to_string();
@@ -95,9 +97,25 @@ fn append_path(original_path: &str, new_path: &str) -> String {
     format!("{}{};", original_path, new_path)
 }
 
+#[cfg(any(windows, test))]
+fn remove_path(original_path: &str, removed_path: &str) -> String {
+    #[cfg(windows)]
+    let normalized_path = removed_path.replace("/", "\\");
+    #[cfg(windows)]
+    let removed_path = normalized_path.as_str();
+
+    let remaining: Vec<&str> = original_path.split(';')
+        .filter(|path| !path.is_empty() && *path != removed_path)
+        .collect();
+    if remaining.is_empty() {
+        return "".to_string();
+    }
+    format!("{};", remaining.join(";"))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::shell::append_path;
+    use crate::shell::{append_path, remove_path};
 
     #[test]
     fn test_append_path() {
@@ -109,6 +127,15 @@ mod tests {
         assert_eq!(append_path("a;c;","b"), "a;c;b;");
     }
 
+    #[test]
+    fn test_remove_path() {
+        assert_eq!(remove_path("",""), "");
+        assert_eq!(remove_path("a;b;","b"), "a;");
+        assert_eq!(remove_path("a;b","a"), "b;");
+        assert_eq!(remove_path("a;c;","b"), "a;c;");
+        assert_eq!(remove_path("b;","b"), "");
+    }
+
 }
 
 #[cfg(windows)]
@@ -126,6 +153,27 @@ pub fn update_env_path(value: &str) {
     update_env_variable("PATH", value);
 }
 
+/// Remove value added by update_env_variable, other entries of the variable are kept.
+#[cfg(windows)]
+pub fn remove_env_variable_value(variable_name: &str, value: &str) {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    let env = hkcu.open_subkey("Environment").unwrap();
+    let env_path:String = env.get_value(variable_name).unwrap_or_default();
+    let updated_env_path = remove_path(env_path.as_str(), value);
+    if updated_env_path != env_path {
+        set_env_variable(variable_name, updated_env_path);
+    }
+}
+
+/// Value of user environment variable stored in registry, independent of the environment of the current process.
+#[cfg(windows)]
+pub fn get_env_variable(variable_name: &str) -> Option<String> {
+    use winreg::{enums::HKEY_CURRENT_USER, RegKey};
+    let hkcu = RegKey::predef(HKEY_CURRENT_USER);
+    hkcu.open_subkey("Environment").ok()?.get_value(variable_name).ok()
+}
+
 #[cfg(unix)]
 pub fn update_env_variable(variable_name: &str, value: &str) {
 }
@@ -135,8 +183,13 @@ pub fn update_env_path(value: &str) {
 }
 
 #[cfg(unix)]
-pub fn set_env_variable(key:&str, value:&str) {
+pub fn remove_env_variable_value(_variable_name: &str, _value: &str) {
+}
 
+/// Variables are not persisted by idf-env on Unix, the shell profile is managed by the user.
+#[cfg(unix)]
+pub fn get_env_variable(_variable_name: &str) -> Option<String> {
+    None
 }
 
 
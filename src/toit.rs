@@ -4,8 +4,10 @@ use clap_nested::{Command, Commander, MultiCommand};
 use dirs::home_dir;
 use std::path::Path;
 use std::fs::{remove_dir_all};
+use crate::config::get_dist_path;
 use crate::package::{prepare_package};
-use crate::shell::{update_env_path};
+use crate::package::staging::is_installed;
+use crate::receipt::{self, Receipt};
 
 struct ToitTools {
     jaguar_dist_file: String,
@@ -27,17 +29,22 @@ fn build_toit_tools() -> ToitTools {
 
 fn install_toit_tools(toit_tools:&ToitTools) {
 
-    if Path::new(&toit_tools.jaguar_destination_dir.as_str()).exists() {
+    if is_installed(Path::new(&toit_tools.jaguar_destination_dir)) {
         println!("Previous installation of Toit - Jaguar exist in: {}", toit_tools.jaguar_destination_dir);
         println!("Please, remove the directory before new installation.");
     } else {
         match prepare_package(toit_tools.jaguar_dist_url.to_string(),
                                      &toit_tools.jaguar_dist_file,
-                                     toit_tools.jaguar_destination_dir.to_string()) {
-                                        Ok(_) => { println!("Toit package ready"); },
-                                        Err(_e) => {
-                                            println!("Unable to prepare the package.");
-                                            return;
+                                     toit_tools.jaguar_destination_dir.to_string(),
+                                     None) {
+                                        Ok(_) => {
+                                            println!("Toit package ready");
+                                            receipt::record(Receipt::new("toit", "jaguar", "latest", &toit_tools.jaguar_dist_url)
+                                                .with_archive(&get_dist_path(&toit_tools.jaguar_dist_file))
+                                                .with_path(&toit_tools.jaguar_destination_dir));
+                                        },
+                                        Err(e) => {
+                                            e.exit("Unable to prepare the package. ");
                                         }
                                      }
     }
@@ -47,11 +54,16 @@ fn install_toit_tools(toit_tools:&ToitTools) {
     #[cfg(unix)]
     println!("export PATH=\"{}:$PATH\"", &toit_tools.jaguar_destination_dir);
 
-    update_env_path(&toit_tools.jaguar_destination_dir);
+    receipt::update_env_path("toit", "jaguar", &toit_tools.jaguar_destination_dir);
 
 }
 
 fn uninstall_toit_tools(toit_tools:&ToitTools) {
+    if receipt::uninstall(|receipt| receipt.group == "toit" && receipt.component == "jaguar") {
+        return;
+    }
+
+    // Installation by older idf-env without receipt
     if Path::new(toit_tools.jaguar_destination_dir.as_str()).exists() {
         println!("Removing: {}", toit_tools.jaguar_destination_dir);
         match remove_dir_all(&toit_tools.jaguar_destination_dir) {